@@ -0,0 +1,26 @@
+//! Make a printable paint by numbers PDF from a photo.
+//!
+//! cargo run --release --example pdf -- photo.jpg puzzle.pdf
+
+use pbn::{pdf_from_image, PbnOptions};
+
+fn main() {
+    let args = std::env::args().collect::<Vec<_>>();
+    if args.len() != 3 {
+        eprintln!("Usage: {} <image> <output.pdf>", args[0]);
+        std::process::exit(1);
+    }
+
+    let input = std::fs::read(&args[1]).expect("Unable to read image");
+    let options = PbnOptions {
+        seed: Some(0),
+        ..PbnOptions::default()
+    };
+    match pdf_from_image(&input, &options) {
+        Ok(pdf) => std::fs::write(&args[2], pdf).expect("Unable to write file"),
+        Err(e) => {
+            eprintln!("{e}");
+            std::process::exit(1);
+        }
+    }
+}
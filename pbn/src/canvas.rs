@@ -1,12 +1,16 @@
 use std::{
-    cell::RefCell,
     cmp,
-    collections::{HashMap, HashSet, VecDeque},
-    rc::Rc,
+    collections::{BinaryHeap, HashMap, VecDeque},
 };
 
-use crate::kmeans;
+use crate::{
+    color::{self, Color, ColorSpace},
+    indexed::IndexedImage,
+    kmeans,
+    regions::RegionMap,
+};
 use image::{Rgb, RgbImage};
+use wasm_bindgen::prelude::*;
 
 /// Shrink an image to a maximum size while maintaining aspect ratio
 pub fn shrink(img: RgbImage, max_size: u32) -> RgbImage {
@@ -17,223 +21,354 @@ pub fn shrink(img: RgbImage, max_size: u32) -> RgbImage {
         return img;
     }
 
+    // Very long, thin images keep at least one pixel across
     let scale = max_size as f32 / cmp::max(width, height) as f32;
     let new_size = (
-        (width as f32 * scale) as u32,
-        (height as f32 * scale) as u32,
+        ((width as f32 * scale) as u32).max(1),
+        ((height as f32 * scale) as u32).max(1),
     );
     println!("Shrinking image to {}x{}...", new_size.0, new_size.1);
 
-    return image::imageops::resize(
+    image::imageops::resize(
         &img,
         new_size.0,
         new_size.1,
         image::imageops::FilterType::Lanczos3,
-    );
+    )
 }
 
-pub fn scale(img: RgbImage, scale: u32) -> RgbImage {
+/// Scale a label map up by a whole factor, keeping the edges hard
+pub fn scale(img: &IndexedImage, scale: u32) -> IndexedImage {
     println!("Scaling image up by {}x...", scale);
     let (width, height) = img.dimensions();
-    let new_size = (width * scale, height * scale);
-    return image::imageops::resize(
-        &img,
-        new_size.0,
-        new_size.1,
-        image::imageops::FilterType::Nearest,
-    );
+    IndexedImage::from_fn(width * scale, height * scale, img.palette.clone(), |x, y| {
+        img.get(x / scale, y / scale)
+    })
 }
 
-/// Replace all pixels in an image with the nearest centroid
-pub fn recolor(img: RgbImage, centroids: &Vec<image::Rgb<u8>>) -> RgbImage {
+/// Replace all pixels in an image with the nearest centroid,
+/// measuring distance in the given color space.
+/// The result is a label map whose palette is the centroids.
+pub fn recolor(img: &RgbImage, centroids: &[Rgb<u8>], space: ColorSpace) -> IndexedImage {
     println!("Replacing colors in image...");
-    let mut new_img = img.clone();
-    for pixel in new_img.pixels_mut() {
-        let mut min_distance = f64::MAX;
-        let mut min_index = 0;
-        for (i, centroid) in centroids.iter().enumerate() {
-            let d = kmeans::distance(pixel, centroid);
-            if d < min_distance {
-                min_distance = d;
-                min_index = i;
+    let centroid_colors = centroids.iter().map(|c| space.from_rgb(c)).collect::<Vec<_>>();
+
+    // Cache the nearest centroid for each color, as most images reuse colors a lot
+    let mut nearest = HashMap::<Rgb<u8>, u16>::new();
+    let indices = img
+        .pixels()
+        .map(|pixel| {
+            *nearest
+                .entry(*pixel)
+                .or_insert_with(|| kmeans::nearest(&space.from_rgb(pixel), &centroid_colors) as u16)
+        })
+        .collect();
+    IndexedImage::new(img.width(), img.height(), indices, centroids.to_vec())
+}
+
+/// How a small area picks the neighboring color it gets merged into
+#[wasm_bindgen]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MergeStrategy {
+    /// The color it shares the most border with
+    LongestBorder,
+    /// The color closest to its own in CIELAB
+    NearestColor,
+    /// Equal parts shared border and CIELAB closeness
+    Weighted,
+    /// The color closest to the area's original pixels, which adds the least quantization error
+    MinError,
+}
+
+// ΔE76 between palette colors rarely goes past about 100, so dividing by this puts the color
+// difference on roughly the same 0 to 1 scale as the share of border in `MergeStrategy::Weighted`
+const WEIGHTED_DELTA_E: f64 = 100.0;
+
+/// Remove all areas in an image that have less than the min defined area.
+/// Areas are visited in scan order, and each small one takes on a neighboring color picked by
+/// the merge strategy, joining any neighbors of that color, until it's big enough.
+/// `source` is the image before quantization, used to measure error.
+pub fn denoise(img: IndexedImage, source: &RgbImage, min_area: u32, strategy: MergeStrategy) -> IndexedImage {
+    println!("Denoising image with minimum area {min_area} pixels...");
+    let mut merger = Merger::new(img, source, strategy);
+
+    // Merge small areas, keeping the small area's id so it can keep growing
+    for id in 0..merger.map.areas().len() as u32 {
+        while merger.map.area(id).pixels > 0 && merger.map.area(id).pixels < min_area {
+            let Some(color) = merger.pick_color(id) else {
+                break;
+            };
+            merger.absorb(id, color);
+        }
+    }
+
+    // Paint every pixel with the color of the area it ended up in
+    merger.into_image()
+}
+
+/// Which areas `reduce_regions` merges first
+#[wasm_bindgen]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MergeOrder {
+    /// The areas with the fewest pixels
+    Smallest,
+    /// The areas whose color is closest to the neighboring color they would take
+    MostSimilar,
+}
+
+/// Merge areas, smallest or most similar first, until there are at most `max_regions`.
+/// Merges that would go below `min_regions` are skipped, as a later one that joins fewer areas may still fit.
+/// Each merge takes a neighboring color picked by the merge strategy, like `denoise`.
+pub fn reduce_regions(
+    img: IndexedImage,
+    source: &RgbImage,
+    min_regions: u32,
+    max_regions: u32,
+    strategy: MergeStrategy,
+    order: MergeOrder,
+) -> IndexedImage {
+    println!("Merging areas down to at most {max_regions} regions, {order:?} first...");
+    let mut merger = Merger::new(img, source, strategy);
+    let mut count = merger.map.areas().len() as u32;
+
+    // When an area should be merged, lowest first, or None once it has been merged away
+    let priority = |merger: &Merger, id: u32| {
+        let area = merger.map.area(id);
+        if area.pixels == 0 {
+            return None;
+        }
+        match order {
+            MergeOrder::Smallest => Some(area.pixels as u64),
+            MergeOrder::MostSimilar => {
+                let color = merger.pick_color(id)?;
+                let delta_e = color::distance(&merger.lab[area.color as usize], &merger.lab[color as usize]);
+                // In thousandths of a ΔE, so the heap can order it
+                Some((delta_e * 1000.0).round() as u64)
             }
         }
-        *pixel = centroids[min_index];
+    };
+    let mut heap = (0..count)
+        .filter_map(|id| Some(cmp::Reverse((priority(&merger, id)?, id))))
+        .collect::<BinaryHeap<_>>();
+    while count > max_regions {
+        let Some(cmp::Reverse((old, id))) = heap.pop() else {
+            break;
+        };
+
+        // Areas grow, get merged away and get new neighbors, so check the entry is still current
+        match priority(&merger, id) {
+            None => continue,
+            Some(current) if current != old => {
+                heap.push(cmp::Reverse((current, id)));
+                continue;
+            }
+            Some(_) => {}
+        }
+        let Some(color) = merger.pick_color(id) else {
+            continue;
+        };
+
+        // Taking a color joins every neighbor with it, which may remove more than one region
+        let joined = merger.joined(id, color).len() as u32;
+        if count - joined < min_regions {
+            continue;
+        }
+        merger.absorb(id, color);
+        count -= joined;
+        if let Some(current) = priority(&merger, id) {
+            heap.push(cmp::Reverse((current, id)));
+        }
+    }
+    if count > max_regions {
+        println!("Stopped at {count} regions, any more merges would go below {min_regions}");
     }
-    new_img
+    println!("Reduced to {count} regions");
+
+    merger.into_image()
 }
 
-/// Remove all areas in an image that have less than the min defined area
-pub fn denoise(img: RgbImage, min_area: u32) -> RgbImage {
-    println!("Denoising image with minimum area {min_area} pixels...");
+/// The state shared by the area merging passes
+struct Merger {
+    map: RegionMap,
+    palette: Vec<Rgb<u8>>,
+    strategy: MergeStrategy,
+    /// The palette in CIELAB
+    lab: Vec<Color>,
+    /// The sum of each area's original colors in CIELAB, so merged areas know their mean color
+    sums: Vec<Color>,
+}
 
-    // Create a new image to store the denoised image
-    // Need to use Rc and RefCell to allow for mutable and immutable borrows (Sometimes i fuckin hate rust)
-    let new_img_ref = Rc::new(RefCell::new(img.clone()));
+impl Merger {
+    fn new(img: IndexedImage, source: &RgbImage, strategy: MergeStrategy) -> Merger {
+        let map = RegionMap::new(&img);
+        let lab = img.palette.iter().map(|c| ColorSpace::Lab.from_rgb(c)).collect::<Vec<_>>();
+
+        let mut lab_cache = HashMap::<Rgb<u8>, Color>::new();
+        let mut sums = vec![[0.0; 3]; map.areas().len()];
+        for (x, y, pixel) in source.enumerate_pixels() {
+            let color = lab_cache.entry(*pixel).or_insert_with(|| ColorSpace::Lab.from_rgb(pixel));
+            let sum = &mut sums[map.label(x, y) as usize];
+            for c in 0..3 {
+                sum[c] += color[c];
+            }
+        }
 
-    // Keep track of visited cells
-    let mut visited = vec![vec![false; img.height() as usize]; img.width() as usize];
+        Merger {
+            map,
+            palette: img.palette,
+            strategy,
+            lab,
+            sums,
+        }
+    }
 
-    // Loop through the image's pixels
-    for loop_y in 0..img.height() {
-        for loop_x in 0..img.width() {
-            // Ignore all cells that are visited
-            if visited[loop_x as usize][loop_y as usize] {
-                continue;
+    /// Pick the neighboring color an area should take, or None if it has no neighbors
+    fn pick_color(&self, id: u32) -> Option<u16> {
+        let area = self.map.area(id);
+
+        // Find how much border is shared with each neighboring color
+        let mut color_counts = HashMap::<u16, u32>::new();
+        for (n, len) in area.neighbors.iter() {
+            *color_counts.entry(self.map.area(*n).color).or_insert(0) += len;
+        }
+        let mut candidates = color_counts.into_iter().collect::<Vec<_>>();
+        candidates.sort_by_key(|(c, _)| self.palette[*c as usize].0);
+        let total = candidates.iter().map(|(_, n)| *n).sum::<u32>() as f64;
+
+        let own = &self.lab[area.color as usize];
+        let mean = self.sums[id as usize].map(|c| c / area.pixels as f64);
+        let lab = &self.lab;
+        let cost = |(c, n): &(u16, u32)| match self.strategy {
+            MergeStrategy::LongestBorder => -(*n as f64),
+            MergeStrategy::NearestColor => color::distance(own, &lab[*c as usize]),
+            MergeStrategy::Weighted => {
+                color::distance(own, &lab[*c as usize]) / WEIGHTED_DELTA_E + 1.0 - *n as f64 / total
             }
-            let mut curr_visited = HashSet::<(usize, usize)>::new();
-            let mut color;
-            let mut new_color_items = HashSet::<(usize, usize)>::new();
-
-            // Block for immutable borrow
-            {
-                let new_img = new_img_ref.borrow();
-
-                // Initialize values required for denoising the current region
-                // We define area separately from curr_visited because of the case of expanding to valid sized areas
-                let mut edge_set = HashSet::<(usize, usize)>::new();
-                let mut area = 0;
-                color = new_img.get_pixel(loop_x, loop_y).clone();
-
-                // Run flood fill
-                let mut queue = VecDeque::<(usize, usize)>::new();
-                queue.push_back((loop_x as usize, loop_y as usize));
-                while !queue.is_empty() {
-                    // Get the next pixel (unwrap bc of is_empty check)
-                    let (x, y) = queue.pop_front().unwrap();
-
-                    // Ignore if in visited in the current flood fill
-                    if curr_visited.contains(&(x, y)) {
-                        continue;
-                    }
-
-                    // Add to edge set if visited or not the same color
-                    if visited[x][y] {
-                        // If it matches the current color, then that means that the new area will be > min_area
-                        // as all visited pixels are part of an area that is > min_area
-                        // However, thinking about it, this case should never happen... (but it does??)
-                        if *new_img.get_pixel(x as u32, y as u32) == color {
-                            area += min_area;
-                        }
-                        edge_set.insert((x, y));
-                        continue;
-                    } else if *new_img.get_pixel(x as u32, y as u32) != color {
-                        edge_set.insert((x, y));
-                        continue;
-                    }
-
-                    // Visit the pixel
-                    curr_visited.insert((x, y));
-                    area += 1;
-
-                    // Add all children to the queue
-                    for (dx, dy) in vec![(0, 1), (1, 0), (0, -1), (-1, 0)] {
-                        let nx = x as i32 + dx;
-                        let ny = y as i32 + dy;
-
-                        // Only add if in bounds
-                        if nx < 0 || ny < 0 || nx >= img.width() as i32 || ny >= img.height() as i32
-                        {
-                            continue;
-                        }
-                        queue.push_back((nx as usize, ny as usize));
-                    }
-                }
-
-                // println!("Edge set: {:?}", edge_set);
-                // println!("Area after initial: {}", area);
-
-                // If the area is below the threshold, we need to expand our area
-                while area < min_area {
-                    // If there are no more edges, we can't expand anymore
-                    if edge_set.is_empty() {
-                        break;
-                    }
-
-                    // Reset new_color_items
-                    new_color_items.clear();
-
-                    // Get the new color as the most common color in the edge set
-                    // Unwrap for max color because we know there is at least one color in the edge set
-                    let mut color_counts = HashMap::<&Rgb<u8>, u32>::new();
-                    for (x, y) in edge_set.iter() {
-                        let color = new_img.get_pixel(*x as u32, *y as u32);
-                        *color_counts.entry(color).or_insert(0) += 1;
-                    }
-                    let new_color = *color_counts.iter().max_by(|a, b| a.1.cmp(b.1)).unwrap().0;
-                    // println!("New color: {:?}", new_color);
-
-                    // Check every edge node, running flood fill on any that are the same color and not visited
-                    let mut queue = VecDeque::from_iter(edge_set.clone());
-                    while !queue.is_empty() {
-                        // Get the next pixel (unwrap bc of is_empty check)
-                        let (x, y) = queue.pop_front().unwrap();
-
-                        // Ignore if the wrong color
-                        if new_img.get_pixel(x as u32, y as u32) != new_color {
-                            continue;
-                        }
-
-                        // If the edge is already visited
-                        if visited[x][y] {
-                            area += min_area;
-                            edge_set.remove(&(x, y));
-                            continue;
-                        }
-
-                        // Otherwise, if node has been visited in this flood fill, ignore
-                        if curr_visited.contains(&(x, y)) {
-                            continue;
-                        }
-
-                        // Otherwise, visit node, increase area, and add children to queue
-                        curr_visited.insert((x, y));
-                        area += 1;
-
-                        // Add all children to the queue
-                        for (dx, dy) in vec![(0, 1), (1, 0), (0, -1), (-1, 0)] {
-                            let nx = x as i32 + dx;
-                            let ny = y as i32 + dy;
-
-                            // Only add if in bounds
-                            if nx < 0
-                                || ny < 0
-                                || nx >= img.width() as i32
-                                || ny >= img.height() as i32
-                            {
-                                continue;
-                            }
-                            queue.push_back((nx as usize, ny as usize));
-                        }
-
-                        // Remove from edge set
-                        edge_set.remove(&(x, y));
-                    }
-
-                    color = *new_color;
-                }
-                // println!("Color: {:?}", color);
-                // println!("Visited: {:?}", curr_visited.len());
-                // println!("Area after edge fill: {:?}", area);
-                // println!("==============================================\n");
+            MergeStrategy::MinError => color::distance_sq(&mean, &lab[*c as usize]),
+        };
+        // Ties are broken by the color itself so the result doesn't depend on hash order
+        candidates
+            .iter()
+            .rev()
+            .min_by(|a, b| cost(a).total_cmp(&cost(b)))
+            .map(|(c, _)| *c)
+    }
+
+    /// The neighbors of an area that have the given color
+    fn joined(&self, id: u32, color: u16) -> Vec<u32> {
+        self.map
+            .area(id)
+            .neighbors
+            .keys()
+            .filter(|n| self.map.area(**n).color == color)
+            .copied()
+            .collect()
+    }
+
+    /// Give an area a new color, joining every neighbor that has it
+    fn absorb(&mut self, id: u32, color: u16) {
+        self.map.set_color(id, color);
+        for other in self.joined(id, color) {
+            let other_sum = self.sums[other as usize];
+            for (sum, other) in self.sums[id as usize].iter_mut().zip(other_sum) {
+                *sum += other;
+            }
+            self.map.merge(other, id);
+        }
+    }
+
+    /// Paint every pixel with the color of the area it ended up in
+    fn into_image(self) -> IndexedImage {
+        self.map.into_image(self.palette)
+    }
+}
+
+/// Remove the parts of areas narrower than `min_width` pixels, so every area can be painted.
+/// An area keeps the pixels covered by a `min_width` square that fits inside it (a morphological opening).
+/// Everything else takes the color of the nearest kept pixel of another color, which absorbs slivers
+/// and cuts narrow necks. This can leave small pieces behind, so denoise afterwards.
+pub fn remove_thin(img: IndexedImage, min_width: u32) -> IndexedImage {
+    println!("Removing areas thinner than {min_width} pixels...");
+    let (width, height) = (img.width() as usize, img.height() as usize);
+    let w = min_width as usize;
+    let color_at = |i: usize| img.get((i % width) as u32, (i / width) as u32);
+
+    // Find the largest square of one color with its bottom right corner at each pixel
+    let mut squares = vec![0usize; width * height];
+    for y in 0..height {
+        for x in 0..width {
+            let i = y * width + x;
+            let color = color_at(i);
+            squares[i] = if x > 0 && y > 0 && color_at(i - 1) == color && color_at(i - width) == color && color_at(i - width - 1) == color {
+                1 + squares[i - 1].min(squares[i - width]).min(squares[i - width - 1])
+            } else {
+                1
+            };
+        }
+    }
+
+    // Cover every min_width square that fits, using a 2D difference array so this stays linear
+    let mut cover = vec![0i32; (width + 1) * (height + 1)];
+    for y in 0..height {
+        for x in 0..width {
+            if squares[y * width + x] >= w {
+                let (x0, y0, x1, y1) = (x + 1 - w, y + 1 - w, x + 1, y + 1);
+                cover[y0 * (width + 1) + x0] += 1;
+                cover[y0 * (width + 1) + x1] -= 1;
+                cover[y1 * (width + 1) + x0] -= 1;
+                cover[y1 * (width + 1) + x1] += 1;
             }
+        }
+    }
+    for y in 0..=height {
+        for x in 1..=width {
+            cover[y * (width + 1) + x] += cover[y * (width + 1) + x - 1];
+        }
+    }
+    for y in 1..=height {
+        for x in 0..=width {
+            cover[y * (width + 1) + x] += cover[(y - 1) * (width + 1) + x];
+        }
+    }
+    let kept = |i: usize| cover[(i / width) * (width + 1) + i % width] > 0;
 
-            // Visit all pixels in the area and color them
-            {
-                let mut new_img_mut = new_img_ref.borrow_mut();
-                for (x, y) in curr_visited.iter() {
-                    // Ignore everything that's already colored correctly
-                    if new_color_items.contains(&(*x, *y)) {
-                        continue;
-                    }
-
-                    visited[*x][*y] = true;
-                    new_img_mut.put_pixel(*x as u32, *y as u32, color);
-                }
+    // Nothing is wide enough, so there's nothing to grow from
+    if !(0..width * height).any(kept) {
+        println!("WARNING: No area is {min_width} pixels wide, skipping");
+        return img;
+    }
+
+    // Grow the kept pixels over the thin ones, breadth first so the nearest color wins.
+    // A thin pixel only takes a color other than its own; its own color just passes through it.
+    let mut colors = (0..width * height).map(color_at).collect::<Vec<_>>();
+    let mut settled = (0..width * height).map(kept).collect::<Vec<_>>();
+    let mut passed = vec![false; width * height];
+    let mut queue = (0..width * height)
+        .filter(|i| settled[*i])
+        .map(|i| (i, colors[i]))
+        .collect::<VecDeque<_>>();
+    while let Some((i, color)) = queue.pop_front() {
+        let (x, y) = (i % width, i / width);
+        let neighbors = [
+            (x > 0).then(|| i - 1),
+            (x + 1 < width).then(|| i + 1),
+            (y > 0).then(|| i - width),
+            (y + 1 < height).then(|| i + width),
+        ];
+        for n in neighbors.into_iter().flatten() {
+            if settled[n] {
+                continue;
+            }
+            if colors[n] != color {
+                colors[n] = color;
+                settled[n] = true;
+                queue.push_back((n, color));
+            } else if !passed[n] {
+                passed[n] = true;
+                queue.push_back((n, color));
             }
         }
     }
 
-    let out = new_img_ref.borrow().clone();
-    out
+    IndexedImage::new(img.width(), img.height(), colors, img.palette)
 }
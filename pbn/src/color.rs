@@ -0,0 +1,183 @@
+use image::Rgb;
+use wasm_bindgen::prelude::*;
+
+/// A color as three floating point components in some color space
+pub type Color = [f64; 3];
+
+/// The color space used to compare and average colors
+#[wasm_bindgen]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ColorSpace {
+    /// Raw sRGB values (0-255), the same as the image data
+    Srgb,
+    /// sRGB with the gamma curve removed (0-1)
+    LinearRgb,
+    /// CIELAB with a D65 white point
+    Lab,
+    /// Björn Ottosson's OKLab
+    Oklab,
+}
+
+impl ColorSpace {
+    /// Convert an sRGB pixel into this color space
+    pub fn from_rgb(self, rgb: &Rgb<u8>) -> Color {
+        let srgb = [rgb[0] as f64, rgb[1] as f64, rgb[2] as f64];
+        match self {
+            ColorSpace::Srgb => srgb,
+            ColorSpace::LinearRgb => srgb.map(to_linear),
+            ColorSpace::Lab => linear_to_lab(srgb.map(to_linear)),
+            ColorSpace::Oklab => linear_to_oklab(srgb.map(to_linear)),
+        }
+    }
+
+    /// Convert a color in this color space back into an sRGB pixel
+    pub fn to_rgb(self, color: &Color) -> Rgb<u8> {
+        let srgb = match self {
+            ColorSpace::Srgb => *color,
+            ColorSpace::LinearRgb => color.map(from_linear),
+            ColorSpace::Lab => lab_to_linear(color).map(from_linear),
+            ColorSpace::Oklab => oklab_to_linear(color).map(from_linear),
+        };
+        Rgb(srgb.map(|c| c.round().clamp(0.0, 255.0) as u8))
+    }
+}
+
+/// Convert an RGB color to a hex string.
+pub fn to_hex(rgb: &Rgb<u8>) -> String {
+    format!("#{:02X}{:02X}{:02X}", rgb[0], rgb[1], rgb[2])
+}
+
+/// Parse a hex string like "#1A2B3C" or "1a2b3c" (or the short "#abc") into an RGB color.
+pub fn from_hex(hex: &str) -> Option<Rgb<u8>> {
+    let hex = hex.trim().trim_start_matches('#');
+    if !hex.is_ascii() {
+        return None;
+    }
+    match hex.len() {
+        6 => Some(Rgb([
+            u8::from_str_radix(&hex[0..2], 16).ok()?,
+            u8::from_str_radix(&hex[2..4], 16).ok()?,
+            u8::from_str_radix(&hex[4..6], 16).ok()?,
+        ])),
+        3 => {
+            let mut rgb = [0; 3];
+            for (i, c) in hex.chars().enumerate() {
+                rgb[i] = c.to_digit(16)? as u8 * 17;
+            }
+            Some(Rgb(rgb))
+        }
+        _ => None,
+    }
+}
+
+/// Compute the Euclidean distance between two colors in the same color space.
+/// For CIELAB colors this is the CIE76 ΔE.
+pub fn distance(a: &Color, b: &Color) -> f64 {
+    distance_sq(a, b).sqrt()
+}
+
+/// Compute the squared Euclidean distance between two colors in the same color space
+pub fn distance_sq(a: &Color, b: &Color) -> f64 {
+    let d0 = a[0] - b[0];
+    let d1 = a[1] - b[1];
+    let d2 = a[2] - b[2];
+    d0 * d0 + d1 * d1 + d2 * d2
+}
+
+/// Convert an sRGB channel (0-255) into linear light (0-1)
+fn to_linear(c: f64) -> f64 {
+    let c = c / 255.0;
+    if c <= 0.04045 {
+        c / 12.92
+    } else {
+        ((c + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+/// Convert a linear light channel (0-1) back into sRGB (0-255)
+fn from_linear(c: f64) -> f64 {
+    let c = c.clamp(0.0, 1.0);
+    let c = if c <= 0.0031308 {
+        c * 12.92
+    } else {
+        1.055 * c.powf(1.0 / 2.4) - 0.055
+    };
+    c * 255.0
+}
+
+// D65 reference white
+const WHITE: Color = [0.95047, 1.0, 1.08883];
+
+// CIELAB constants (6/29)^3 and (29/6)^2 / 3
+const LAB_EPSILON: f64 = 216.0 / 24389.0;
+const LAB_KAPPA: f64 = 24389.0 / 27.0;
+
+fn linear_to_lab(rgb: Color) -> Color {
+    // Linear sRGB to XYZ
+    let x = 0.4124564 * rgb[0] + 0.3575761 * rgb[1] + 0.1804375 * rgb[2];
+    let y = 0.2126729 * rgb[0] + 0.7151522 * rgb[1] + 0.0721750 * rgb[2];
+    let z = 0.0193339 * rgb[0] + 0.1191920 * rgb[1] + 0.9503041 * rgb[2];
+
+    let f = |t: f64| {
+        if t > LAB_EPSILON {
+            t.cbrt()
+        } else {
+            (LAB_KAPPA * t + 16.0) / 116.0
+        }
+    };
+    let fx = f(x / WHITE[0]);
+    let fy = f(y / WHITE[1]);
+    let fz = f(z / WHITE[2]);
+
+    [116.0 * fy - 16.0, 500.0 * (fx - fy), 200.0 * (fy - fz)]
+}
+
+fn lab_to_linear(lab: &Color) -> Color {
+    let fy = (lab[0] + 16.0) / 116.0;
+    let fx = fy + lab[1] / 500.0;
+    let fz = fy - lab[2] / 200.0;
+
+    let f_inv = |t: f64| {
+        let t3 = t * t * t;
+        if t3 > LAB_EPSILON {
+            t3
+        } else {
+            (116.0 * t - 16.0) / LAB_KAPPA
+        }
+    };
+    let x = f_inv(fx) * WHITE[0];
+    let y = f_inv(fy) * WHITE[1];
+    let z = f_inv(fz) * WHITE[2];
+
+    // XYZ to linear sRGB
+    [
+        3.2404542 * x - 1.5371385 * y - 0.4985314 * z,
+        -0.9692660 * x + 1.8760108 * y + 0.0415560 * z,
+        0.0556434 * x - 0.2040259 * y + 1.0572252 * z,
+    ]
+}
+
+fn linear_to_oklab(rgb: Color) -> Color {
+    let l = (0.4122214708 * rgb[0] + 0.5363325363 * rgb[1] + 0.0514459929 * rgb[2]).cbrt();
+    let m = (0.2119034982 * rgb[0] + 0.6806995451 * rgb[1] + 0.1073969566 * rgb[2]).cbrt();
+    let s = (0.0883024619 * rgb[0] + 0.2817188376 * rgb[1] + 0.6299787005 * rgb[2]).cbrt();
+
+    [
+        0.2104542553 * l + 0.7936177850 * m - 0.0040720468 * s,
+        1.9779984951 * l - 2.4285922050 * m + 0.4505937099 * s,
+        0.0259040371 * l + 0.7827717662 * m - 0.8086757660 * s,
+    ]
+}
+
+fn oklab_to_linear(lab: &Color) -> Color {
+    let l = lab[0] + 0.3963377774 * lab[1] + 0.2158037573 * lab[2];
+    let m = lab[0] - 0.1055613458 * lab[1] - 0.0638541728 * lab[2];
+    let s = lab[0] - 0.0894841775 * lab[1] - 1.2914855480 * lab[2];
+    let (l, m, s) = (l * l * l, m * m * m, s * s * s);
+
+    [
+        4.0767416621 * l - 3.3077115913 * m + 0.2309699292 * s,
+        -1.2684380046 * l + 2.6097574011 * m - 0.3413193965 * s,
+        -0.0041960863 * l - 0.7034186147 * m + 1.7076147010 * s,
+    ]
+}
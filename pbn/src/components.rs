@@ -0,0 +1,98 @@
+use crate::indexed::IndexedImage;
+
+/// Disjoint sets with path halving and union by size
+pub struct UnionFind {
+    parent: Vec<u32>,
+    size: Vec<u32>,
+}
+
+impl UnionFind {
+    pub fn new(n: usize) -> UnionFind {
+        UnionFind {
+            parent: (0..n as u32).collect(),
+            size: vec![1; n],
+        }
+    }
+
+    /// Add a new set and return its id
+    pub fn push(&mut self) -> u32 {
+        let id = self.parent.len() as u32;
+        self.parent.push(id);
+        self.size.push(1);
+        id
+    }
+
+    /// Find the root of the set `i` is in
+    pub fn find(&mut self, mut i: u32) -> u32 {
+        while self.parent[i as usize] != i {
+            let grandparent = self.parent[self.parent[i as usize] as usize];
+            self.parent[i as usize] = grandparent;
+            i = grandparent;
+        }
+        i
+    }
+
+    /// Join the sets `a` and `b` are in, returning the new root
+    pub fn union(&mut self, a: u32, b: u32) -> u32 {
+        let (a, b) = (self.find(a), self.find(b));
+        if a == b {
+            return a;
+        }
+        let (big, small) = if self.size[a as usize] >= self.size[b as usize] { (a, b) } else { (b, a) };
+        self.parent[small as usize] = big;
+        self.size[big as usize] += self.size[small as usize];
+        big
+    }
+
+    /// Make `root` the root of `other`'s set, keeping `root` as the id of the joined set
+    pub fn union_into(&mut self, other: u32, root: u32) {
+        let (other, root) = (self.find(other), self.find(root));
+        if other != root {
+            self.parent[other as usize] = root;
+            self.size[root as usize] += self.size[other as usize];
+        }
+    }
+}
+
+/// Label the 4-connected areas of the same color with two-pass union-find.
+/// Returns the area id of every pixel, with ids numbered in scan order, and the pixel count of each area.
+pub fn label(img: &IndexedImage) -> (Vec<u32>, Vec<u32>) {
+    let (width, height) = (img.width() as usize, img.height() as usize);
+    let mut labels = vec![0u32; width * height];
+    let mut sets = UnionFind::new(0);
+
+    // First pass: give each pixel the label of its left or upper neighbor,
+    // noting when both match but have different labels
+    for y in 0..height {
+        for x in 0..width {
+            let i = y * width + x;
+            let color = img.get(x as u32, y as u32);
+            let left = (x > 0 && img.get(x as u32 - 1, y as u32) == color).then(|| labels[i - 1]);
+            let up = (y > 0 && img.get(x as u32, y as u32 - 1) == color).then(|| labels[i - width]);
+            labels[i] = match (left, up) {
+                (Some(l), Some(u)) => {
+                    sets.union(l, u);
+                    l
+                }
+                (Some(l), None) => l,
+                (None, Some(u)) => u,
+                (None, None) => sets.push(),
+            };
+        }
+    }
+
+    // Second pass: replace labels with their root, numbering roots in the order they're first seen
+    let mut ids = vec![u32::MAX; sets.parent.len()];
+    let mut areas = Vec::<u32>::new();
+    for label in labels.iter_mut() {
+        let root = sets.find(*label) as usize;
+        if ids[root] == u32::MAX {
+            ids[root] = areas.len() as u32;
+            areas.push(0);
+        }
+        *label = ids[root];
+        areas[*label as usize] += 1;
+    }
+
+    (labels, areas)
+}
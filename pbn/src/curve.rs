@@ -0,0 +1,209 @@
+/// A point in SVG coordinates
+pub type Point = (f64, f64);
+
+/// A piece of an outline, ending at its last point
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Segment {
+    Line(Point),
+    /// Two control points, then the end point
+    Cubic(Point, Point, Point),
+}
+
+/// A smoothed outline: a start point and the segments that follow it
+#[derive(Clone, Debug, PartialEq)]
+pub struct Curve {
+    pub start: Point,
+    pub segments: Vec<Segment>,
+}
+
+impl Curve {
+    /// The end point of the curve
+    pub fn end(&self) -> Point {
+        match self.segments.last() {
+            Some(Segment::Line(p)) | Some(Segment::Cubic(_, _, p)) => *p,
+            None => self.start,
+        }
+    }
+
+    /// The same curve walked the other way
+    pub fn reversed(&self) -> Curve {
+        let mut segments = Vec::with_capacity(self.segments.len());
+        let mut from = self.start;
+        for segment in self.segments.iter() {
+            // Each segment reversed ends where it started
+            segments.push(match segment {
+                Segment::Line(_) => Segment::Line(from),
+                Segment::Cubic(c1, c2, _) => Segment::Cubic(*c2, *c1, from),
+            });
+            from = match segment {
+                Segment::Line(p) | Segment::Cubic(_, _, p) => *p,
+            };
+        }
+        segments.reverse();
+        Curve {
+            start: self.end(),
+            segments,
+        }
+    }
+
+    /// Twice the signed area of a closed curve, with y pointing down, taken through the segment ends.
+    /// How far the Béziers bulge is ignored, which is enough to tell a curve that collapsed flat.
+    pub fn area(&self) -> f64 {
+        let mut points = vec![self.start];
+        points.extend(self.segments.iter().map(|segment| match segment {
+            Segment::Line(p) | Segment::Cubic(_, _, p) => *p,
+        }));
+        twice_area(&points)
+    }
+
+    /// Write the segments as SVG path commands, without the starting move
+    pub fn push_segments(&self, out: &mut String) {
+        for segment in self.segments.iter() {
+            match segment {
+                Segment::Line((x, y)) => out.push_str(&format!(" L {} {}", num(*x), num(*y))),
+                Segment::Cubic((ax, ay), (bx, by), (x, y)) => out.push_str(&format!(
+                    " C {} {} {} {} {} {}",
+                    num(*ax),
+                    num(*ay),
+                    num(*bx),
+                    num(*by),
+                    num(*x),
+                    num(*y)
+                )),
+            }
+        }
+    }
+}
+
+/// Format a coordinate with at most two decimals, dropping trailing zeros
+pub fn num(v: f64) -> String {
+    let s = format!("{:.2}", v);
+    let s = s.trim_end_matches('0').trim_end_matches('.');
+    if s == "-0" {
+        "0".to_string()
+    } else {
+        s.to_string()
+    }
+}
+
+// Turns sharper than this many degrees are kept as corners instead of being smoothed
+const CORNER_ANGLE: f64 = 60.0;
+
+/// Smooth a polyline the way Potrace does: simplify it with Douglas-Peucker so no point moves
+/// more than `tolerance`, then join the points with cubic Béziers, keeping sharp turns as corners.
+/// The ends never move, so curves that meet at their ends stay joined.
+/// A tolerance of 0 keeps the polyline as straight lines, and so does a loop that would simplify down to
+/// nothing, like the outline of an area thinner than the tolerance.
+pub fn fit(points: &[Point], tolerance: f64) -> Curve {
+    let lines = |points: &[Point]| Curve {
+        start: points[0],
+        segments: points.iter().skip(1).map(|p| Segment::Line(*p)).collect(),
+    };
+    if tolerance <= 0.0 || points.len() < 3 {
+        return lines(points);
+    }
+
+    // Simplify. A closed loop is split at its furthest point, so both halves have distinct ends
+    let closed = points[0] == points[points.len() - 1];
+    let simple = if closed {
+        let far = (1..points.len() - 1)
+            .max_by(|a, b| dist_sq(points[0], points[*a]).total_cmp(&dist_sq(points[0], points[*b])))
+            .unwrap();
+        let mut simple = douglas_peucker(&points[..=far], tolerance);
+        simple.pop();
+        simple.extend(douglas_peucker(&points[far..], tolerance));
+        if twice_area(&simple) == 0.0 {
+            return lines(points);
+        }
+        simple
+    } else {
+        douglas_peucker(points, tolerance)
+    };
+    if simple.len() < 3 {
+        return lines(&simple);
+    }
+
+    // The tangent at each point, or None for corners and loose ends
+    let n = simple.len();
+    let neighbors = |i: usize| {
+        if i > 0 && i < n - 1 {
+            Some((simple[i - 1], simple[i + 1]))
+        } else if closed && n > 3 {
+            // The ends of a loop are the same point, between the second and second to last
+            Some((simple[n - 2], simple[1]))
+        } else {
+            None
+        }
+    };
+    let tangents = (0..n)
+        .map(|i| {
+            let (prev, next) = neighbors(i)?;
+            let (a, b) = ((simple[i].0 - prev.0, simple[i].1 - prev.1), (next.0 - simple[i].0, next.1 - simple[i].1));
+            let cos = (a.0 * b.0 + a.1 * b.1) / (a.0.hypot(a.1) * b.0.hypot(b.1));
+            if cos < CORNER_ANGLE.to_radians().cos() {
+                return None;
+            }
+            Some(((next.0 - prev.0) / 6.0, (next.1 - prev.1) / 6.0))
+        })
+        .collect::<Vec<_>>();
+
+    // Catmull-Rom splines through the points, as Béziers
+    let segments = (0..n - 1)
+        .map(|i| {
+            let (a, b) = (simple[i], simple[i + 1]);
+            match (tangents[i], tangents[i + 1]) {
+                (None, None) => Segment::Line(b),
+                (ta, tb) => {
+                    let (ta, tb) = (ta.unwrap_or((0.0, 0.0)), tb.unwrap_or((0.0, 0.0)));
+                    Segment::Cubic((a.0 + ta.0, a.1 + ta.1), (b.0 - tb.0, b.1 - tb.1), b)
+                }
+            }
+        })
+        .collect();
+
+    Curve {
+        start: simple[0],
+        segments,
+    }
+}
+
+/// Twice the signed area of the polygon through the points, closed back to the first
+fn twice_area(points: &[Point]) -> f64 {
+    points
+        .iter()
+        .zip(points.iter().cycle().skip(1))
+        .map(|(a, b)| a.0 * b.1 - b.0 * a.1)
+        .sum()
+}
+
+fn dist_sq(a: Point, b: Point) -> f64 {
+    (a.0 - b.0).powi(2) + (a.1 - b.1).powi(2)
+}
+
+/// Keep the fewest points such that every dropped point is within `tolerance` of the result
+fn douglas_peucker(points: &[Point], tolerance: f64) -> Vec<Point> {
+    let (a, b) = (points[0], points[points.len() - 1]);
+    let (dx, dy) = (b.0 - a.0, b.1 - a.1);
+    let len = dx.hypot(dy);
+
+    // Find the point furthest from the line between the ends
+    let mut far = (0, 0.0);
+    for (i, p) in points.iter().enumerate().take(points.len() - 1).skip(1) {
+        let d = if len == 0.0 {
+            dist_sq(a, *p).sqrt()
+        } else {
+            ((p.0 - a.0) * dy - (p.1 - a.1) * dx).abs() / len
+        };
+        if d > far.1 {
+            far = (i, d);
+        }
+    }
+
+    if far.1 <= tolerance {
+        return vec![a, b];
+    }
+    let mut out = douglas_peucker(&points[..=far.0], tolerance);
+    out.pop();
+    out.extend(douglas_peucker(&points[far.0..], tolerance));
+    out
+}
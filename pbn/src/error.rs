@@ -0,0 +1,73 @@
+use std::fmt;
+
+use image::ImageError;
+
+/// Everything that can go wrong while making a paint by numbers.
+/// Messages are meant to be shown to the user as is.
+#[derive(Debug)]
+pub enum PbnError {
+    /// The input bytes couldn't be decoded as an image
+    Decode(ImageError),
+    /// The output image couldn't be encoded
+    Encode(ImageError),
+    /// The image has no pixels
+    EmptyImage,
+    /// The requested number of colors can't be used
+    InvalidColorCount(i64),
+    /// The smallest palette size to try is bigger than the largest
+    InvalidColorRange(u32, u32),
+    /// A palette or pigment entry couldn't be parsed
+    InvalidPaint(String),
+    /// More base pigments were given than recipes can be computed from
+    TooManyPigments(usize),
+    /// The image has more colors than a flat image can be numbered with
+    TooManyColors(usize),
+    /// A setting is out of range: its JS name, the value given and what it should be
+    InvalidOption {
+        name: &'static str,
+        value: f64,
+        expected: &'static str,
+    },
+}
+
+impl fmt::Display for PbnError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PbnError::Decode(e) => write!(f, "Could not read the image, is it a PNG, JPEG or other common format? ({e})"),
+            PbnError::Encode(e) => write!(f, "Could not save the image ({e})"),
+            PbnError::EmptyImage => write!(f, "The image is empty"),
+            PbnError::InvalidColorCount(k) => {
+                write!(f, "The number of colors must be between 1 and {}, got {k}", crate::MAX_COLORS)
+            }
+            PbnError::InvalidColorRange(min, max) => {
+                write!(f, "The fewest colors to try ({min}) can't be more than the most ({max})")
+            }
+            PbnError::InvalidPaint(p) => write!(
+                f,
+                "Could not understand the paint \"{p}\", use a hex code like #E30022, a name and hex code like Cadmium Red=#E30022, or a CSS color name"
+            ),
+            PbnError::TooManyPigments(n) => write!(
+                f,
+                "Mixing recipes can use at most {} base pigments, got {n}",
+                crate::mixing::MAX_BASE_PIGMENTS
+            ),
+            PbnError::TooManyColors(n) => write!(
+                f,
+                "The image has {n} colors, more than the {} a flat image can have, so flatten it first",
+                u16::MAX as usize + 1
+            ),
+            PbnError::InvalidOption { name, value, expected } => write!(f, "{name} must be {expected}, got {value}"),
+        }
+    }
+}
+
+impl std::error::Error for PbnError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            PbnError::Decode(e) | PbnError::Encode(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+pub type Result<T> = std::result::Result<T, PbnError>;
@@ -0,0 +1,185 @@
+use image::{Rgb, RgbImage};
+use wasm_bindgen::prelude::*;
+
+/// Edge-preserving filters that flatten noise before quantization
+#[wasm_bindgen]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Smoothing {
+    /// Leave the image as is
+    None,
+    /// Average nearby pixels, weighted by how close they are in position and color
+    Bilateral,
+    /// Move each pixel to the mean of the nearby pixels of similar color until it settles
+    MeanShift,
+    /// Take the mean of whichever window quadrant has the least variance
+    Kuwahara,
+    /// Take the per-channel median of the window
+    Median,
+}
+
+/// The largest window radius the filters accept. They look at every pixel of the window for every
+/// pixel of the image, so the work grows with the square of the radius.
+pub const MAX_RADIUS: u32 = 10;
+
+/// Smooth an image with the given filter.
+/// `radius` is the window radius in pixels. `strength` is how far apart in RGB two colors can be
+/// and still get averaged, used by the bilateral and mean shift filters.
+pub fn smooth(img: RgbImage, smoothing: Smoothing, radius: u32, strength: f64) -> RgbImage {
+    if smoothing == Smoothing::None || radius == 0 {
+        return img;
+    }
+    println!("Smoothing image with {smoothing:?} filter, radius {radius}...");
+    match smoothing {
+        Smoothing::None => img,
+        Smoothing::Bilateral => bilateral(&img, radius, strength),
+        Smoothing::MeanShift => mean_shift(&img, radius, strength),
+        Smoothing::Kuwahara => kuwahara(&img, radius),
+        Smoothing::Median => median(&img, radius),
+    }
+}
+
+/// The window around a pixel, clamped to the image
+fn window(img: &RgbImage, x: u32, y: u32, radius: u32) -> impl Iterator<Item = (u32, u32)> {
+    let (width, height) = img.dimensions();
+    let (x0, x1) = (x.saturating_sub(radius), x.saturating_add(radius).min(width - 1));
+    let (y0, y1) = (y.saturating_sub(radius), y.saturating_add(radius).min(height - 1));
+    (y0..=y1).flat_map(move |wy| (x0..=x1).map(move |wx| (wx, wy)))
+}
+
+fn color_dist_sq(a: &Rgb<u8>, b: &[f64; 3]) -> f64 {
+    (0..3).map(|c| (a[c] as f64 - b[c]).powi(2)).sum()
+}
+
+fn to_rgb(color: [f64; 3]) -> Rgb<u8> {
+    Rgb(color.map(|c| c.round().clamp(0.0, 255.0) as u8))
+}
+
+fn bilateral(img: &RgbImage, radius: u32, strength: f64) -> RgbImage {
+    let space = 2.0 * (radius as f64 / 2.0).max(0.5).powi(2);
+    let range = 2.0 * strength.max(1.0).powi(2);
+    RgbImage::from_fn(img.width(), img.height(), |x, y| {
+        let center = img.get_pixel(x, y).0.map(|c| c as f64);
+        let mut sum = [0.0; 3];
+        let mut total = 0.0;
+        for (wx, wy) in window(img, x, y, radius) {
+            let pixel = img.get_pixel(wx, wy);
+            let d = (wx as f64 - x as f64).powi(2) + (wy as f64 - y as f64).powi(2);
+            let weight = (-d / space - color_dist_sq(pixel, &center) / range).exp();
+            for c in 0..3 {
+                sum[c] += pixel[c] as f64 * weight;
+            }
+            total += weight;
+        }
+        to_rgb(sum.map(|s| s / total))
+    })
+}
+
+fn mean_shift(img: &RgbImage, radius: u32, strength: f64) -> RgbImage {
+    let range = strength.max(1.0).powi(2);
+    RgbImage::from_fn(img.width(), img.height(), |x, y| {
+        let (mut px, mut py) = (x as f64, y as f64);
+        let mut color = img.get_pixel(x, y).0.map(|c| c as f64);
+
+        // Shift towards the local mode, giving up after a few steps
+        for _ in 0..5 {
+            let mut sum = [0.0; 5];
+            let mut count = 0.0;
+            let (cx, cy) = (px.round() as u32, py.round() as u32);
+            for (wx, wy) in window(img, cx, cy, radius) {
+                let pixel = img.get_pixel(wx, wy);
+                if color_dist_sq(pixel, &color) > range {
+                    continue;
+                }
+                sum[0] += wx as f64;
+                sum[1] += wy as f64;
+                for c in 0..3 {
+                    sum[c + 2] += pixel[c] as f64;
+                }
+                count += 1.0;
+            }
+            if count == 0.0 {
+                break;
+            }
+
+            let new_color = [sum[2] / count, sum[3] / count, sum[4] / count];
+            let moved = (sum[0] / count - px).abs() + (sum[1] / count - py).abs();
+            let shift = (0..3).map(|c| (new_color[c] - color[c]).abs()).sum::<f64>();
+            px = sum[0] / count;
+            py = sum[1] / count;
+            color = new_color;
+            if moved < 0.5 && shift < 1.0 {
+                break;
+            }
+        }
+        to_rgb(color)
+    })
+}
+
+fn kuwahara(img: &RgbImage, radius: u32) -> RgbImage {
+    let (width, height) = img.dimensions();
+    let stride = width as usize + 1;
+
+    // Summed area tables of each channel and its square, so every quadrant is O(1)
+    let mut sums = vec![[0.0f64; 6]; stride * (height as usize + 1)];
+    for y in 0..height as usize {
+        for x in 0..width as usize {
+            let pixel = img.get_pixel(x as u32, y as u32);
+            let i = (y + 1) * stride + x + 1;
+            for c in 0..3 {
+                let v = pixel[c] as f64;
+                for (k, value) in [(c, v), (c + 3, v * v)] {
+                    sums[i][k] = value + sums[i - 1][k] + sums[i - stride][k] - sums[i - stride - 1][k];
+                }
+            }
+        }
+    }
+    let rect = |x0: u32, y0: u32, x1: u32, y1: u32| {
+        let (x0, y0, x1, y1) = (x0 as usize, y0 as usize, x1 as usize + 1, y1 as usize + 1);
+        let mut out = [0.0; 6];
+        for (k, o) in out.iter_mut().enumerate() {
+            *o = sums[y1 * stride + x1][k] - sums[y0 * stride + x1][k] - sums[y1 * stride + x0][k]
+                + sums[y0 * stride + x0][k];
+        }
+        (out, ((x1 - x0) * (y1 - y0)) as f64)
+    };
+
+    RgbImage::from_fn(width, height, |x, y| {
+        let (left, right) = (x.saturating_sub(radius), x.saturating_add(radius).min(width - 1));
+        let (top, bottom) = (y.saturating_sub(radius), y.saturating_add(radius).min(height - 1));
+        let quadrants = [
+            rect(left, top, x, y),
+            rect(x, top, right, y),
+            rect(left, y, x, bottom),
+            rect(x, y, right, bottom),
+        ];
+
+        // Use the mean of the flattest quadrant
+        let (best, n) = quadrants
+            .iter()
+            .min_by(|(a, an), (b, bn)| {
+                let variance = |s: &[f64; 6], n: f64| (0..3).map(|c| s[c + 3] / n - (s[c] / n).powi(2)).sum::<f64>();
+                variance(a, *an).total_cmp(&variance(b, *bn))
+            })
+            .unwrap();
+        to_rgb([best[0] / n, best[1] / n, best[2] / n])
+    })
+}
+
+fn median(img: &RgbImage, radius: u32) -> RgbImage {
+    let mut values = [Vec::new(), Vec::new(), Vec::new()];
+    RgbImage::from_fn(img.width(), img.height(), |x, y| {
+        for v in values.iter_mut() {
+            v.clear();
+        }
+        for (wx, wy) in window(img, x, y, radius) {
+            let pixel = img.get_pixel(wx, wy);
+            for c in 0..3 {
+                values[c].push(pixel[c]);
+            }
+        }
+        Rgb(std::array::from_fn(|c| {
+            let mid = values[c].len() / 2;
+            *values[c].select_nth_unstable(mid).1
+        }))
+    })
+}
@@ -0,0 +1,40 @@
+use std::collections::HashMap;
+
+use image::{Rgb, RgbImage};
+
+/// A unique color in an image along with the number of pixels that have it
+pub type HistogramEntry = (Rgb<u8>, u32);
+
+/// Build a color histogram of an image.
+/// Entries are sorted by color so that the result doesn't depend on hash order.
+pub fn histogram(img: &RgbImage) -> Vec<HistogramEntry> {
+    let mut counts = HashMap::<Rgb<u8>, u32>::new();
+    for pixel in img.pixels() {
+        *counts.entry(*pixel).or_insert(0) += 1;
+    }
+
+    let mut hist = counts.into_iter().collect::<Vec<_>>();
+    hist.sort_unstable_by_key(|(c, _)| c.0);
+    hist
+}
+
+/// Merge colors that share the top `bits` bits of every channel into their weighted average.
+/// This trades a little accuracy for far fewer entries when a histogram is processed many times.
+pub fn coarsen(hist: &[HistogramEntry], bits: u8) -> Vec<HistogramEntry> {
+    let shift = 8 - bits;
+    let mut bins = HashMap::<[u8; 3], ([u64; 3], u64)>::new();
+    for (color, n) in hist.iter() {
+        let (sum, count) = bins.entry(color.0.map(|c| c >> shift)).or_insert(([0; 3], 0));
+        for c in 0..3 {
+            sum[c] += color[c] as u64 * *n as u64;
+        }
+        *count += *n as u64;
+    }
+
+    let mut coarse = bins
+        .into_values()
+        .map(|(sum, count)| (Rgb(sum.map(|s| ((s + count / 2) / count) as u8)), count as u32))
+        .collect::<Vec<_>>();
+    coarse.sort_unstable_by_key(|(c, _)| c.0);
+    coarse
+}
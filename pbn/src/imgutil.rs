@@ -1,20 +1,33 @@
-use image::{DynamicImage, ImageError, ImageFormat, ImageReader, ImageBuffer, Rgb};
+use image::{DynamicImage, ImageFormat, ImageReader, ImageBuffer, Rgb, RgbImage};
 use std::io::Cursor;
 
+use crate::error::{PbnError, Result};
+
 /// Converts a Vec<u8> to a DynamicImage.
-pub fn vec_to_image(data: &Vec<u8>) -> Result<DynamicImage, ImageError> {
+pub fn vec_to_image(data: &[u8]) -> Result<DynamicImage> {
     let img = ImageReader::new(std::io::Cursor::new(data))
-        .with_guessed_format()?  // Automatically detects format
-        .decode()?;  // Decodes into a DynamicImage
+        .with_guessed_format()
+        .map_err(|e| PbnError::Decode(e.into()))?  // Automatically detects format
+        .decode()
+        .map_err(PbnError::Decode)?;  // Decodes into a DynamicImage
+    Ok(img)
+}
+
+/// Converts a Vec<u8> to an RGB image, making sure it isn't empty.
+pub fn vec_to_rgb(data: &[u8]) -> Result<RgbImage> {
+    let img = vec_to_image(data)?.to_rgb8();
+    if img.width() == 0 || img.height() == 0 {
+        return Err(PbnError::EmptyImage);
+    }
     Ok(img)
 }
 
 /// Converts a DynamicImage to a Vec<u8> in PNG format.
-pub fn image_to_vec(image: &ImageBuffer<Rgb<u8>, Vec<u8>>, format: ImageFormat) -> Vec<u8> {
+pub fn image_to_vec(image: &ImageBuffer<Rgb<u8>, Vec<u8>>, format: ImageFormat) -> Result<Vec<u8>> {
     let mut buffer = Cursor::new(Vec::new());
 
     // Encode the image into the buffer
-    image.write_to(&mut buffer, format).expect("Failed to encode image");
+    image.write_to(&mut buffer, format).map_err(PbnError::Encode)?;
 
-    buffer.into_inner() // Return the Vec<u8>
+    Ok(buffer.into_inner()) // Return the Vec<u8>
 }
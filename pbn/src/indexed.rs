@@ -0,0 +1,135 @@
+use std::collections::{hash_map::Entry, HashMap, HashSet};
+
+use image::{Rgb, RgbImage};
+
+use crate::error::{PbnError, Result};
+
+/// An image where every pixel is an index into a palette.
+/// This is the label map the pipeline works on once colors have been quantized.
+#[derive(Clone, Debug, PartialEq)]
+pub struct IndexedImage {
+    width: u32,
+    height: u32,
+    indices: Vec<u16>,
+    pub palette: Vec<Rgb<u8>>,
+}
+
+impl IndexedImage {
+    /// Create an image from row-major palette indices
+    pub fn new(width: u32, height: u32, indices: Vec<u16>, palette: Vec<Rgb<u8>>) -> IndexedImage {
+        assert_eq!(indices.len(), (width * height) as usize);
+        IndexedImage {
+            width,
+            height,
+            indices,
+            palette,
+        }
+    }
+
+    /// Create an image by calling `f` for the palette index of every pixel
+    pub fn from_fn(
+        width: u32,
+        height: u32,
+        palette: Vec<Rgb<u8>>,
+        f: impl Fn(u32, u32) -> u16,
+    ) -> IndexedImage {
+        let indices = (0..height)
+            .flat_map(|y| (0..width).map(move |x| (x, y)))
+            .map(|(x, y)| f(x, y))
+            .collect();
+        IndexedImage::new(width, height, indices, palette)
+    }
+
+    /// Index a flat RGB image by its colors.
+    /// Colors in `palette` come first, in the order given, then any other colors in scan order.
+    /// Palette colors that aren't in the image are left out.
+    /// Fails if the image has more colors than an index can hold, as a photo that isn't flat would.
+    pub fn from_rgb(img: &RgbImage, palette: &[Rgb<u8>]) -> Result<IndexedImage> {
+        // Find the colors used in the image, in scan order
+        let mut used = Vec::<Rgb<u8>>::new();
+        let mut lookup = HashMap::<Rgb<u8>, u16>::new();
+        for pixel in img.pixels() {
+            if let Entry::Vacant(e) = lookup.entry(*pixel) {
+                e.insert(0);
+                used.push(*pixel);
+            }
+        }
+        if used.len() > u16::MAX as usize + 1 {
+            return Err(PbnError::TooManyColors(used.len()));
+        }
+
+        // Put the palette colors first
+        let mut seen = HashSet::new();
+        let colors = palette
+            .iter()
+            .filter(|c| lookup.contains_key(c))
+            .chain(used.iter())
+            .filter(|c| seen.insert(**c))
+            .copied()
+            .collect::<Vec<_>>();
+        for (i, color) in colors.iter().enumerate() {
+            lookup.insert(*color, i as u16);
+        }
+
+        let indices = img.pixels().map(|p| lookup[p]).collect();
+        Ok(IndexedImage::new(img.width(), img.height(), indices, colors))
+    }
+
+    pub fn width(&self) -> u32 {
+        self.width
+    }
+
+    pub fn height(&self) -> u32 {
+        self.height
+    }
+
+    pub fn dimensions(&self) -> (u32, u32) {
+        (self.width, self.height)
+    }
+
+    /// Get the palette index of a pixel
+    pub fn get(&self, x: u32, y: u32) -> u16 {
+        self.indices[(y * self.width + x) as usize]
+    }
+
+    /// Drop palette colors that no pixel uses, keeping the order of the rest
+    pub fn compact(&mut self) {
+        let mut used = vec![false; self.palette.len()];
+        for i in self.indices.iter() {
+            used[*i as usize] = true;
+        }
+        let order = (0..self.palette.len() as u16).filter(|i| used[*i as usize]).collect::<Vec<_>>();
+        self.reorder(&order);
+    }
+
+    /// Renumber the palette in the order colors first appear in the image, dropping unused colors.
+    /// This matches how `from_rgb` numbers colors that aren't in the palette.
+    pub fn scan_order(&mut self) {
+        let mut used = vec![false; self.palette.len()];
+        let mut order = Vec::new();
+        for i in self.indices.iter() {
+            if !used[*i as usize] {
+                used[*i as usize] = true;
+                order.push(*i);
+            }
+        }
+        self.reorder(&order);
+    }
+
+    /// Make `order[n]` the new palette index `n`. Colors left out of `order` must be unused.
+    fn reorder(&mut self, order: &[u16]) {
+        let mut remap = vec![0u16; self.palette.len()];
+        for (new, old) in order.iter().enumerate() {
+            remap[*old as usize] = new as u16;
+        }
+        for i in self.indices.iter_mut() {
+            *i = remap[*i as usize];
+        }
+        self.palette = order.iter().map(|i| self.palette[*i as usize]).collect();
+    }
+
+    /// Convert back into an RGB image
+    pub fn to_rgb(&self) -> RgbImage {
+        RgbImage::from_fn(self.width, self.height, |x, y| self.palette[self.get(x, y) as usize])
+    }
+}
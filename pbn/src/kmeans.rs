@@ -1,130 +1,142 @@
 use image::Rgb;
-use rand::{distr::{weighted::WeightedIndex, Distribution}, Rng};
-
-const MAX_ITER: i32 = 100;
+use rand::{distr::{weighted::WeightedIndex, Distribution}, SeedableRng};
+use rand_chacha::ChaCha8Rng;
+
+use crate::{
+    color::{self, Color, ColorSpace},
+    histogram::HistogramEntry,
+};
+
+/// Create the random number generator for the pipeline.
+/// The same seed always produces the same sequence; no seed uses fresh entropy.
+/// ChaCha8 is named rather than `ChaCha8Rng` because its output is promised not to change between
+/// rand versions or platforms, so a seeded puzzle stays the same after a dependency update.
+pub fn make_rng(seed: Option<u64>) -> ChaCha8Rng {
+    match seed {
+        Some(seed) => ChaCha8Rng::seed_from_u64(seed),
+        None => ChaCha8Rng::from_rng(&mut rand::rng()),
+    }
+}
 
-pub fn kmeans(arr: &Vec<&Rgb<u8>>, k: i32) -> Vec<Rgb<u8>> {
-    println!("Running K-means with k={k}...");
-    let mut centroids = Vec::<Rgb<u8>>::new();
+/// Run K-means clustering on a color histogram, comparing and averaging
+/// colors in the given color space. Each unique color is weighted by its pixel count.
+/// Stops after `max_iter` iterations even if the clusters haven't converged.
+pub fn kmeans(
+    hist: &[HistogramEntry],
+    k: usize,
+    space: ColorSpace,
+    max_iter: u32,
+    rng: &mut ChaCha8Rng,
+) -> Vec<Rgb<u8>> {
+    println!("Running K-means with k={k} on {} unique colors in {space:?}...", hist.len());
+
+    // There can't be more clusters than unique colors
+    let k = k.min(hist.len());
+    if k == 0 {
+        return Vec::new();
+    }
 
-    // Clone arr
-    let mut not_picked = arr.clone();
+    // Convert all unique colors into the clustering color space
+    let points = hist.iter().map(|(c, _)| space.from_rgb(c)).collect::<Vec<_>>();
+    let weights = hist.iter().map(|(_, n)| *n as f64).collect::<Vec<_>>();
+    let mut centroids = Vec::<Color>::new();
 
-    // Pick first centroid randomly
+    // Pick first centroid randomly, weighted by how many pixels have each color
     // We will run the K-means++ algorithm for initial centroid selection
-    let first_index = rand::rng().random_range(..arr.len());
-    centroids.push(*not_picked.remove(first_index));
+    let dist = WeightedIndex::new(&weights).unwrap();
+    centroids.push(points[dist.sample(rng)]);
+
+    // Track the squared distance from each point to its nearest centroid
+    let mut min_distances = points
+        .iter()
+        .map(|p| color::distance_sq(p, &centroids[0]))
+        .collect::<Vec<_>>();
 
     // Loop until we have k centroids
     for _ in 1..k {
-        // Calculate the distance from each point to the nearest centroid
-        // and store the minimum distance squared
-        let mut distances = Vec::<f64>::new();
-        for point in not_picked.iter() {
-            let mut min_distance = f64::MAX;
-            for centroid in centroids.iter() {
-                let d = distance(point, centroid);
-                if d < min_distance {
-                    min_distance = d;
-                }
-            }
-            distances.push(min_distance*min_distance);
-        }
-
         // Created a weighted probability distribution based on the distances
-        let dist = WeightedIndex::new(&distances).unwrap();
-        let mut rng = rand::rng();
-        let index = dist.sample(&mut rng);
-        centroids.push(*not_picked.remove(index));
+        // and the number of pixels with each color
+        let seed_weights = min_distances
+            .iter()
+            .zip(weights.iter())
+            .map(|(d, w)| d * w)
+            .collect::<Vec<_>>();
+        // This fails when every color is already a centroid, so stop early
+        let Ok(dist) = WeightedIndex::new(&seed_weights) else {
+            break;
+        };
+        let centroid = points[dist.sample(rng)];
+        centroids.push(centroid);
+
+        // Update the nearest distances with the new centroid
+        for (point, min_distance) in points.iter().zip(min_distances.iter_mut()) {
+            *min_distance = min_distance.min(color::distance_sq(point, &centroid));
+        }
     }
 
     // We will now run the K-means algorithm
+    let mut assignments = vec![usize::MAX; points.len()];
     let mut changed = true;
     let mut iter = 0;
-    while changed && iter < MAX_ITER {
+    while changed && iter < max_iter {
         changed = false;
 
-        // Create clusters from the centroids
-        let mut clusters = vec![Vec::<&Rgb<u8>>::new(); k as usize];
+        // Weighted sums and total weights of each cluster
+        let mut sums = vec![[0.0; 3]; centroids.len()];
+        let mut totals = vec![0.0; centroids.len()];
 
         // Assign each point to the nearest centroid
-        for point in arr.iter() {
-            let mut min_distance = f64::MAX;
-            let mut min_index = 0;
-            for (i, centroid) in centroids.iter().enumerate() {
-                let d = distance(point, centroid);
-                if d < min_distance {
-                    min_distance = d;
-                    min_index = i;
-                }
+        for (j, point) in points.iter().enumerate() {
+            let min_index = nearest(point, &centroids);
+            if assignments[j] != min_index {
+                assignments[j] = min_index;
+                changed = true;
+            }
+            for c in 0..3 {
+                sums[min_index][c] += point[c] * weights[j];
             }
-            clusters[min_index].push(point);
+            totals[min_index] += weights[j];
         }
 
         // Calculate the new centroids
-        for (i, cluster) in clusters.iter().enumerate() {
+        for i in 0..centroids.len() {
             // If the cluster is empty, pick the point furthest from all centroids
-            if cluster.is_empty() {
+            if totals[i] == 0.0 {
                 let mut max_distance = 0.0;
                 let mut max_index = 0;
-                for (j, point) in arr.iter().enumerate() {
-                    let mut min_distance = f64::MAX;
-                    for centroid in centroids.iter() {
-                        let d = distance(point, centroid);
-                        if d < min_distance {
-                            min_distance = d;
-                        }
-                    }
+                for (j, point) in points.iter().enumerate() {
+                    let min_distance = color::distance_sq(point, &centroids[nearest(point, &centroids)]);
                     if min_distance > max_distance {
                         max_distance = min_distance;
                         max_index = j;
                     }
                 }
-                centroids[i] = *arr[max_index];
+                centroids[i] = points[max_index];
                 changed = true;
                 continue;
             }
 
-            // Calculate the new centroid
-            let new_centroid = re_centroid(cluster);
-            if centroids[i] != new_centroid {
-                centroids[i] = new_centroid;
-                changed = true;
-            }
+            // Calculate the new centroid as the weighted average of the cluster
+            centroids[i] = sums[i].map(|c| c / totals[i]);
         }
 
         iter += 1;
         // println!("KMeans Iteration: {}", iter);
     }
 
-    return centroids;
+    centroids.iter().map(|c| space.to_rgb(c)).collect()
 }
 
-/// Compute the "distance" between two colors
-pub fn distance(a: &Rgb<u8>, b: &Rgb<u8>) -> f64 {
-    let r = a[0] as f64 - b[0] as f64;
-    let g = a[1] as f64 - b[1] as f64;
-    let b = a[2] as f64 - b[2] as f64;
-
-    return (r * r + g * g + b * b).sqrt();
-}
-
-/// Calculate the centroid of a cluster based on
-/// the average of all points in the cluster
-fn re_centroid(cluster: &Vec<&Rgb<u8>>) -> Rgb<u8> {
-    let mut r = 0;
-    let mut g = 0;
-    let mut b = 0;
-    for point in cluster.iter() {
-        r += point[0] as i32;
-        g += point[1] as i32;
-        b += point[2] as i32;
-    }
-    let len = cluster.len() as i32;
-    if len > 0 {
-        r /= len;
-        g /= len;
-        b /= len;
+/// Find the index of the centroid nearest to a point
+pub fn nearest(point: &Color, centroids: &[Color]) -> usize {
+    let mut min_distance = f64::MAX;
+    let mut min_index = 0;
+    for (i, centroid) in centroids.iter().enumerate() {
+        let d = color::distance_sq(point, centroid);
+        if d < min_distance {
+            min_distance = d;
+            min_index = i;
+        }
     }
-    return Rgb([r as u8, g as u8, b as u8]);
+    min_index
 }
@@ -0,0 +1,299 @@
+use std::collections::HashMap;
+
+use crate::{curve::Point, options::PbnOptions, regions::RegionMap};
+
+/// A box as (min x, min y, max x, max y)
+pub type Rect = (f64, f64, f64, f64);
+
+// Space kept clear around each label, in SVG pixels
+const GAP: f64 = 1.0;
+
+// Size of the cells placed labels are bucketed in, in SVG pixels
+const CELL: f64 = 32.0;
+
+/// A number drawn in the SVG
+#[derive(Clone, Debug)]
+pub struct Label {
+    /// The area it labels
+    pub area: u32,
+    /// 0 for the area's main label, then 1 and up for extra labels
+    pub index: usize,
+    pub x: f64,
+    pub y: f64,
+    pub size: f64,
+    pub number: u32,
+    /// Radius of the biggest circle around the spot that stays in the area, in SVG pixels
+    pub room: f64,
+    /// Where the leader line starts in the area, for callouts placed outside it
+    pub leader: Option<Point>,
+}
+
+impl Label {
+    /// The box the text covers, taking digits to be 0.6 of the font size wide
+    pub fn bbox(&self) -> Rect {
+        let half_width = text_width(self.number, self.size) / 2.0;
+        let half_height = self.size / 2.0;
+        (self.x - half_width, self.y - half_height, self.x + half_width, self.y + half_height)
+    }
+
+    /// The leader line from the area to the nearest point of the label's box
+    pub fn leader_line(&self) -> Option<(Point, Point)> {
+        let (ax, ay) = self.leader?;
+        let (x0, y0, x1, y1) = self.bbox();
+        Some(((ax, ay), (ax.clamp(x0, x1), ay.clamp(y0, y1))))
+    }
+}
+
+/// How wide a number is at a font size
+pub fn text_width(number: u32, size: f64) -> f64 {
+    0.6 * size * number.to_string().len() as f64
+}
+
+/// The biggest font size whose number fits in a circle of `radius`
+pub fn fit_size(radius: f64, number: u32) -> f64 {
+    let digits = number.to_string().len() as f64;
+    2.0 * radius / (0.36 * digits * digits + 1.0).sqrt()
+}
+
+/// The labels and leader lines placed so far, bucketed by position to keep new ones clear of them quickly
+#[derive(Default)]
+pub struct Placed {
+    boxes: Vec<Rect>,
+    lines: Vec<(Point, Point)>,
+    box_cells: HashMap<(i64, i64), Vec<usize>>,
+    line_cells: HashMap<(i64, i64), Vec<usize>>,
+}
+
+impl Placed {
+    pub fn add(&mut self, label: &Label) {
+        let bbox = label.bbox();
+        for cell in cells(bbox) {
+            self.box_cells.entry(cell).or_default().push(self.boxes.len());
+        }
+        self.boxes.push(bbox);
+        if let Some(line) = label.leader_line() {
+            for cell in cells(line_bbox(line)) {
+                self.line_cells.entry(cell).or_default().push(self.lines.len());
+            }
+            self.lines.push(line);
+        }
+    }
+
+    /// Whether a label or its leader line would overlap anything placed so far
+    pub fn collides(&self, label: &Label) -> bool {
+        let (x0, y0, x1, y1) = label.bbox();
+        let padded = (x0 - GAP, y0 - GAP, x1 + GAP, y1 + GAP);
+        if self.near_boxes(padded).any(|b| overlaps(padded, *b)) || self.near_lines(padded).any(|l| hits_rect(*l, padded)) {
+            return true;
+        }
+        match label.leader_line() {
+            Some(line) => {
+                let bbox = line_bbox(line);
+                self.near_boxes(bbox).any(|b| hits_rect(line, *b)) || self.near_lines(bbox).any(|l| crosses(line, *l))
+            }
+            None => false,
+        }
+    }
+
+    fn near_boxes(&self, area: Rect) -> impl Iterator<Item = &Rect> {
+        cells(area)
+            .filter_map(|cell| self.box_cells.get(&cell))
+            .flatten()
+            .map(|i| &self.boxes[*i])
+    }
+
+    fn near_lines(&self, area: Rect) -> impl Iterator<Item = &(Point, Point)> {
+        cells(area)
+            .filter_map(|cell| self.line_cells.get(&cell))
+            .flatten()
+            .map(|i| &self.lines[*i])
+    }
+}
+
+/// The grid cells a box touches
+fn cells((x0, y0, x1, y1): Rect) -> impl Iterator<Item = (i64, i64)> {
+    let (cx0, cy0) = ((x0 / CELL).floor() as i64, (y0 / CELL).floor() as i64);
+    let (cx1, cy1) = ((x1 / CELL).floor() as i64, (y1 / CELL).floor() as i64);
+    (cy0..=cy1).flat_map(move |cy| (cx0..=cx1).map(move |cx| (cx, cy)))
+}
+
+fn line_bbox((a, b): (Point, Point)) -> Rect {
+    (a.0.min(b.0), a.1.min(b.1), a.0.max(b.0), a.1.max(b.1))
+}
+
+/// Lay out every label so that none overlap, and return the labels to draw and whether each area got its number.
+/// Main labels go first, biggest first. Each is tried at its spot and then at the roomiest other spots in
+/// its area, shrinking step by step down to the minimum font size. Then extra labels that still fit are kept.
+/// Main labels left over become callouts if those are on, or go at their spot if that's clear even though
+/// they spill out of the area. Any others are drawn at their spot anyway, but reported as unlabeled.
+/// `dist` is each pixel's chessboard distance to its area's border.
+pub fn arrange(map: &RegionMap, dist: &[u32], scale: u32, labels: Vec<Label>, options: &PbnOptions) -> (Vec<Label>, Vec<bool>) {
+    println!("Laying out labels...");
+    let (main, extra): (Vec<_>, Vec<_>) = labels.into_iter().partition(|l| l.index == 0);
+    let mut order = main.iter().collect::<Vec<_>>();
+    order.sort_by(|a, b| b.size.total_cmp(&a.size).then(a.area.cmp(&b.area)));
+
+    let mut placed = Placed::default();
+    let mut out = Vec::new();
+    let mut labeled = vec![false; map.areas().len()];
+    let mut left = Vec::new();
+    for label in order {
+        match place_in_area(map, dist, scale, label, &placed, options.min_font_size) {
+            Some(label) => {
+                placed.add(&label);
+                labeled[label.area as usize] = true;
+                out.push(label);
+            }
+            None => left.push(label),
+        }
+    }
+    for label in extra {
+        if labeled[label.area as usize] && !placed.collides(&label) {
+            placed.add(&label);
+            out.push(label);
+        }
+    }
+
+    for label in left {
+        let label = Label {
+            size: options.min_font_size,
+            ..label.clone()
+        };
+        let found = match options.callouts {
+            true => callout(map, dist, scale, &label, &placed, options.callout_margin),
+            false => None,
+        };
+        let found = found.or_else(|| (!placed.collides(&label)).then(|| label.clone()));
+        labeled[label.area as usize] = found.is_some();
+        let label = found.unwrap_or(label);
+        placed.add(&label);
+        out.push(label);
+    }
+    (out, labeled)
+}
+
+/// Find a clear spot for a label inside its area, at the biggest size that fits there
+fn place_in_area(map: &RegionMap, dist: &[u32], scale: u32, label: &Label, placed: &Placed, min_size: f64) -> Option<Label> {
+    let mut sizes = Vec::new();
+    let mut size = label.size;
+    while size > min_size {
+        sizes.push(size);
+        size = (size * 0.8 * 100.0).round() / 100.0;
+    }
+    sizes.push(min_size);
+
+    // The roomiest pixels of the area after its own spot
+    let width = map.dimensions().0;
+    let (x0, y0, x1, y1) = map.area(label.area).bbox;
+    let mut spots = Vec::new();
+    for y in y0..=y1 {
+        for x in x0..=x1 {
+            let d = dist[(y * width + x) as usize];
+            if map.label(x, y) == label.area && d > 1 {
+                spots.push(((x as f64 + 0.5) * scale as f64, (y as f64 + 0.5) * scale as f64, (d as f64 - 0.5) * scale as f64));
+            }
+        }
+    }
+    spots.sort_by(|a, b| b.2.total_cmp(&a.2));
+    spots.truncate(32);
+    spots.insert(0, (label.x, label.y, label.room));
+
+    for size in sizes {
+        for (x, y, room) in spots.iter().copied() {
+            let spot = Label { x, y, size, room, ..label.clone() };
+            if fit_size(room, label.number) >= size && !placed.collides(&spot) {
+                return Some(spot);
+            }
+        }
+    }
+    None
+}
+
+/// Move the label of an area too small to hold it to a nearby spot with room, joined to the area by a
+/// leader line. Spots are tried in widening rings around the label, inside other areas with room for it,
+/// then in a margin of `margin` SVG pixels around the image. `dist` is each pixel's chessboard distance
+/// to its area's border. Returns None when every spot is taken.
+pub fn callout(map: &RegionMap, dist: &[u32], scale: u32, label: &Label, placed: &Placed, margin: f64) -> Option<Label> {
+    let (width, height) = map.dimensions();
+    let scale = scale as f64;
+    let anchor = (label.x, label.y);
+    let (half_width, half_height) = (text_width(label.number, label.size) / 2.0, label.size / 2.0);
+    let moved = |x: f64, y: f64| Label {
+        x,
+        y,
+        leader: Some(anchor),
+        ..label.clone()
+    };
+
+    // Nearby spots in other areas, snapped to pixel centers
+    for step in 1..=8 {
+        let r = step as f64 * label.size * 1.5;
+        for a in 0..16 {
+            let angle = a as f64 * std::f64::consts::PI / 8.0;
+            let (x, y) = (anchor.0 + r * angle.cos(), anchor.1 + r * angle.sin());
+            if x < 0.0 || y < 0.0 || x >= width as f64 * scale || y >= height as f64 * scale {
+                continue;
+            }
+            let (px, py) = ((x / scale) as u32, (y / scale) as u32);
+            let room = (dist[(py * width + px) as usize] as f64 - 0.5) * scale;
+            if map.label(px, py) == label.area || room < half_width.max(half_height) {
+                continue;
+            }
+            let spot = moved((px as f64 + 0.5) * scale, (py as f64 + 0.5) * scale);
+            if !placed.collides(&spot) {
+                return Some(spot);
+            }
+        }
+    }
+
+    // Spots along the middle of the margin, nearest first
+    if margin < label.size || margin < 2.0 * half_width {
+        return None;
+    }
+    let (w, h) = (width as f64 * scale, height as f64 * scale);
+    let mut spots = Vec::new();
+    // The top and bottom run into the corners
+    let mut t = -margin + half_width;
+    while t <= w + margin - half_width {
+        spots.push((t, -margin / 2.0));
+        spots.push((t, h + margin / 2.0));
+        t += label.size / 2.0;
+    }
+    let mut t = half_height;
+    while t <= h - half_height {
+        spots.push((-margin / 2.0, t));
+        spots.push((w + margin / 2.0, t));
+        t += label.size / 2.0;
+    }
+    let to_anchor = |(x, y): &Point| (x - anchor.0).hypot(y - anchor.1);
+    spots.sort_by(|a, b| to_anchor(a).total_cmp(&to_anchor(b)));
+    spots.into_iter().map(|(x, y)| moved(x, y)).find(|spot| !placed.collides(spot))
+}
+
+fn overlaps(a: Rect, b: Rect) -> bool {
+    a.0 < b.2 && b.0 < a.2 && a.1 < b.3 && b.1 < a.3
+}
+
+/// Whether a line segment passes through a box, by clipping it to each side in turn
+fn hits_rect((a, b): (Point, Point), (x0, y0, x1, y1): Rect) -> bool {
+    let (dx, dy) = (b.0 - a.0, b.1 - a.1);
+    let (mut t0, mut t1) = (0.0f64, 1.0f64);
+    for (p, q) in [(-dx, a.0 - x0), (dx, x1 - a.0), (-dy, a.1 - y0), (dy, y1 - a.1)] {
+        if p == 0.0 {
+            if q < 0.0 {
+                return false;
+            }
+        } else if p < 0.0 {
+            t0 = t0.max(q / p);
+        } else {
+            t1 = t1.min(q / p);
+        }
+    }
+    t0 < t1
+}
+
+/// Whether two line segments cross
+fn crosses((a, b): (Point, Point), (c, d): (Point, Point)) -> bool {
+    let side = |p: Point, q: Point, r: Point| ((q.0 - p.0) * (r.1 - p.1) - (q.1 - p.1) * (r.0 - p.0)).signum();
+    side(a, b, c) * side(a, b, d) < 0.0 && side(c, d, a) * side(c, d, b) < 0.0
+}
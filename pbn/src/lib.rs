@@ -1,20 +1,69 @@
 mod canvas;
+mod color;
+mod components;
+mod curve;
+mod error;
+mod filter;
+mod histogram;
+mod indexed;
 mod kmeans;
+mod layout;
+mod median_cut;
+mod mixing;
+mod octree;
+mod options;
+mod palette;
+mod palette_size;
+mod pdf;
+mod polylabel;
+mod quantize;
+mod regions;
 mod svg;
+mod trace;
 mod imgutil;
+mod wu;
 
 use wasm_bindgen::prelude::*;
-use kmeans::kmeans;
+
+pub use canvas::{MergeOrder, MergeStrategy};
+pub use color::ColorSpace;
+pub use error::PbnError;
+pub use filter::Smoothing;
+pub use indexed::IndexedImage;
+pub use mixing::MixRecipe;
+pub use options::{Difficulty, FlatFormat, PaperSize, PbnOptions};
+pub use palette_size::{PaletteObjective, PaletteSizeReport};
+pub use quantize::QuantizerKind;
+pub use regions::{Area, RegionMap};
+pub use svg::Region;
+pub use trace::{trace, Boundaries, Edge, Ring, OUTSIDE};
 
 #[wasm_bindgen]
 pub struct SvgData {
     svg: String,
     colors: Vec<String>,
+    names: Vec<String>,
+    recipes: Vec<MixRecipe>,
+    regions: Vec<Region>,
+    flat: Option<Vec<u8>>,
 }
 
 impl SvgData {
-    pub fn new(svg: String, colors: Vec<String>) -> SvgData {
-        SvgData { svg, colors }
+    pub fn new(
+        svg: String,
+        colors: Vec<String>,
+        names: Vec<String>,
+        recipes: Vec<MixRecipe>,
+        regions: Vec<Region>,
+    ) -> SvgData {
+        SvgData {
+            svg,
+            colors,
+            names,
+            recipes,
+            regions,
+            flat: None,
+        }
     }
 }
 
@@ -29,6 +78,143 @@ impl SvgData {
     pub fn colors(&self) -> js_sys::Array {
         self.colors.iter().map(|c| JsValue::from(c.as_str())).collect()
     }
+
+    /// The paint name for each color, or its hex code if no palette was given
+    #[wasm_bindgen(getter)]
+    pub fn names(&self) -> js_sys::Array {
+        self.names.iter().map(|c| JsValue::from(c.as_str())).collect()
+    }
+
+    /// How to mix each color from the base pigments, empty if no pigments were given
+    #[wasm_bindgen(getter)]
+    pub fn recipes(&self) -> Vec<MixRecipe> {
+        self.recipes.clone()
+    }
+
+    /// Every area in the SVG, in the same order as the paths
+    #[wasm_bindgen(getter)]
+    pub fn regions(&self) -> Vec<Region> {
+        self.regions.clone()
+    }
+
+    /// Ids of the areas whose number couldn't be placed clear of the others
+    #[wasm_bindgen(getter)]
+    pub fn unlabeled(&self) -> Vec<u32> {
+        self.regions.iter().filter(|r| !r.labeled).map(|r| r.id).collect()
+    }
+
+    /// The flat image, only set by `image_to_puzzle` with `includeFlat`
+    #[wasm_bindgen(getter)]
+    pub fn flat(&self) -> Option<Vec<u8>> {
+        self.flat.clone()
+    }
+}
+
+/// A flat image and what the flattening ended up with
+#[wasm_bindgen]
+pub struct FlatImage {
+    data: Vec<u8>,
+    region_count: u32,
+    min_region_area: u32,
+}
+
+#[wasm_bindgen]
+impl FlatImage {
+    /// The encoded image
+    #[wasm_bindgen(getter)]
+    pub fn data(&self) -> Vec<u8> {
+        self.data.clone()
+    }
+
+    /// How many regions the image has
+    #[wasm_bindgen(getter, js_name = regionCount)]
+    pub fn region_count(&self) -> u32 {
+        self.region_count
+    }
+
+    /// Pixels in the smallest region, before scaling
+    #[wasm_bindgen(getter, js_name = minRegionArea)]
+    pub fn min_region_area(&self) -> u32 {
+        self.min_region_area
+    }
+}
+
+/// Parse a list of paint strings, see `palette::parse_paint` for the accepted forms
+fn parse_paints(palette: &[String]) -> error::Result<Vec<palette::Paint>> {
+    palette
+        .iter()
+        .map(|p| palette::parse_paint(p).ok_or_else(|| PbnError::InvalidPaint(p.clone())))
+        .collect()
+}
+
+// More colors than this take too long to quantize and are too many to paint
+const MAX_COLORS: i64 = 256;
+
+/// Make sure the number of colors is usable
+fn check_color_count(k: i64) -> error::Result<usize> {
+    if !(1..=MAX_COLORS).contains(&k) {
+        return Err(PbnError::InvalidColorCount(k));
+    }
+    Ok(k as usize)
+}
+
+/// Make sure a range of palette sizes is usable and not backwards
+fn check_color_range(min_k: u32, max_k: u32) -> error::Result<()> {
+    check_color_count(min_k as i64)?;
+    check_color_count(max_k as i64)?;
+    if min_k > max_k {
+        return Err(PbnError::InvalidColorRange(min_k, max_k));
+    }
+    Ok(())
+}
+
+/// Make sure every setting is in range, so bad values from JS are reported instead of hanging or
+/// giving garbage. Every entry point calls this once before doing any work.
+fn check_options(options: &PbnOptions) -> error::Result<()> {
+    let check = |ok: bool, name: &'static str, value: f64, expected: &'static str| match ok {
+        true => Ok(()),
+        false => Err(PbnError::InvalidOption { name, value, expected }),
+    };
+    let positive = |v: f64| v.is_finite() && v > 0.0;
+    let non_negative = |v: f64| v.is_finite() && v >= 0.0;
+
+    check_color_count(options.k as i64)?;
+    if options.pigments.len() > mixing::MAX_BASE_PIGMENTS {
+        return Err(PbnError::TooManyPigments(options.pigments.len()));
+    }
+    check(options.max_size > 0, "maxSize", options.max_size as f64, "at least 1")?;
+    check(options.scale > 0, "scale", options.scale as f64, "at least 1")?;
+    check(
+        options.min_width <= options.max_size,
+        "minWidth",
+        options.min_width as f64,
+        "at most maxSize",
+    )?;
+    let iterations = options.max_iterations;
+    check((1..=1000).contains(&iterations), "maxIterations", iterations as f64, "between 1 and 1000")?;
+    check(
+        options.max_regions == 0 || options.min_regions <= options.max_regions,
+        "minRegions",
+        options.min_regions as f64,
+        "at most maxRegions",
+    )?;
+    let radius = options.smoothing_radius;
+    check(radius <= filter::MAX_RADIUS, "smoothingRadius", radius as f64, "at most 10")?;
+    let strength = options.smoothing_strength;
+    check(non_negative(strength), "smoothingStrength", strength, "0 or more")?;
+    check(non_negative(options.stroke_width), "strokeWidth", options.stroke_width, "0 or more")?;
+    let tolerance = options.curve_tolerance;
+    check(non_negative(tolerance), "curveTolerance", tolerance, "0 or more")?;
+    check(positive(options.min_font_size), "minFontSize", options.min_font_size, "more than 0")?;
+    check(
+        positive(options.max_font_size) && options.max_font_size >= options.min_font_size,
+        "maxFontSize",
+        options.max_font_size,
+        "at least minFontSize",
+    )?;
+    let spacing = options.label_spacing;
+    check(non_negative(spacing), "labelSpacing", spacing, "0 or more")?;
+    check(non_negative(options.callout_margin), "calloutMargin", options.callout_margin, "0 or more")
 }
 
 #[wasm_bindgen]
@@ -38,62 +224,272 @@ pub fn test() -> String {
     "Hello from Rust!".to_string()
 }
 
-#[wasm_bindgen]
-pub fn img_to_flat(input: Vec<u8>, k: i32, min_area: u32) -> Vec<u8> {
-    console_error_panic_hook::set_once();
+/// Quantize and denoise an image into a label map at its working size.
+/// Shared by `img_to_flat` and `image_to_puzzle`.
+fn flatten(input: &[u8], options: &PbnOptions) -> error::Result<indexed::IndexedImage> {
+    let paints = parse_paints(&options.palette)?;
+    let k = options.k as usize;
 
     // Open the image
-    let img = imgutil::vec_to_image(&input).unwrap();
-    let img_rgb = img.to_rgb8();
+    let img_rgb = imgutil::vec_to_rgb(input)?;
 
     // Print out width and height
     println!("Dimensions: {:?}", img_rgb.dimensions());
 
     // Shrink image
-    let img_rgb = canvas::shrink(img_rgb, 600);
+    let img_rgb = canvas::shrink(img_rgb, options.max_size);
+
+    // Flatten noise so it doesn't turn into speckles
+    let img_rgb = filter::smooth(img_rgb, options.smoothing, options.smoothing_radius, options.smoothing_strength);
 
-    // Get a flat list of all pixels
-    let pixels = img_rgb.pixels().collect::<Vec<_>>();
-    println!("Total Pixels: {:?}", pixels.len());
+    // Count the unique colors, as clustering only needs each color once
+    let hist = histogram::histogram(&img_rgb);
+    println!("Total Pixels: {:?}", img_rgb.len() / 3);
+    println!("Unique Colors: {:?}", hist.len());
 
-    // Run K-means clustering to compute the dominant colors
-    let centroids = kmeans(&pixels, k);
+    let centroids = if paints.is_empty() {
+        // Quantize the colors to compute the dominant colors
+        // The seed makes the output reproducible byte for byte
+        let mut rng = kmeans::make_rng(options.seed);
+        options.quantizer.quantizer(options).quantize(&hist, k, &mut rng)
+    } else {
+        // Use the k paints that best reproduce the image
+        let hist = histogram::coarsen(&hist, 6);
+        palette::best_subset(&hist, &paints, k, options.color_space)
+            .iter()
+            .map(|i| paints[*i].color)
+            .collect()
+    };
     println!("Centroids: {:?}", centroids.len());
 
     // Replace all pixels with the nearest centroid
-    let img_rgb = canvas::recolor(img_rgb, &centroids);
+    let img = canvas::recolor(&img_rgb, &centroids, options.color_space);
 
     // Remove all areas that have less than the min defined area
-    let img_rgb = canvas::denoise(img_rgb, min_area);
+    let mut img = canvas::denoise(img, &img_rgb, options.min_area, options.merge_strategy);
+
+    // Absorb slivers too thin to paint, then clean up the pieces that leaves
+    if options.min_width > 1 {
+        img = canvas::remove_thin(img, options.min_width);
+        img = canvas::denoise(img, &img_rgb, options.min_area, options.merge_strategy);
+    }
 
-    // Scale up the image 4x
-    let img_rgb = canvas::scale(img_rgb, 4);
+    // Merge down to the number of regions asked for
+    if options.max_regions > 0 {
+        img = canvas::reduce_regions(
+            img,
+            &img_rgb,
+            options.min_regions,
+            options.max_regions,
+            options.merge_strategy,
+            options.merge_order,
+        );
+    }
+
+    // Denoising can remove colors entirely, so don't number them
+    // Paints keep the palette order, computed colors are numbered in the order they appear
+    if paints.is_empty() {
+        img.scan_order();
+    } else {
+        img.compact();
+    }
     println!("Done flattening image!");
+    Ok(img)
+}
+
+/// The hex code of each color, and its name: the paint it came from, or the hex code again
+fn color_names(colors: &[image::Rgb<u8>], paints: &[palette::Paint]) -> (Vec<String>, Vec<String>) {
+    let hex = colors.iter().map(color::to_hex).collect::<Vec<_>>();
+    let names = colors
+        .iter()
+        .zip(hex.iter())
+        .map(|(c, h)| {
+            paints
+                .iter()
+                .find(|p| p.color == *c)
+                .map_or_else(|| h.clone(), |p| p.name.clone())
+        })
+        .collect();
+    (hex, names)
+}
+
+/// Build the SVG and the color info for a label map, scaled up by `scale`
+fn puzzle_svg(img: &indexed::IndexedImage, scale: u32, options: &PbnOptions) -> error::Result<SvgData> {
+    let paints = parse_paints(&options.palette)?;
+    let pigments = parse_paints(&options.pigments)?;
+
+    // Convert the image to SVG
+    let (svg_data, regions) = svg::img_to_svg(img, scale, options);
+    let colors = &img.palette;
+
+    // Name each color after its paint, falling back to the hex code
+    let (hex, names) = color_names(colors, &paints);
+
+    // Work out how to mix each color
+    let recipes = if pigments.is_empty() {
+        Vec::new()
+    } else {
+        mixing::recipes(colors, &pigments)
+    };
+
+    Ok(SvgData::new(svg_data, hex, names, recipes, regions))
+}
+
+/// Make sure the paper is a real size and the margins leave room to print on
+fn check_paper(options: &PbnOptions) -> error::Result<()> {
+    let (width, height) = options.paper_size.size_mm(options);
+    for (name, value) in [("paperWidth", width), ("paperHeight", height)] {
+        if !(value.is_finite() && value > 0.0) {
+            return Err(PbnError::InvalidOption { name, value, expected: "more than 0" });
+        }
+    }
+    let margin = options.page_margin;
+    if !(margin.is_finite() && margin >= 0.0 && 2.0 * margin < width.min(height)) {
+        return Err(PbnError::InvalidOption {
+            name: "pageMargin",
+            value: margin,
+            expected: "0 or more and less than half the paper",
+        });
+    }
+    Ok(())
+}
+
+/// Build the printable PDF for a label map, scaled up by `scale`
+fn puzzle_pdf(img: &indexed::IndexedImage, scale: u32, options: &PbnOptions) -> error::Result<Vec<u8>> {
+    check_paper(options)?;
+    let paints = parse_paints(&options.palette)?;
+    let drawing = svg::draw(img, scale, options);
+    let (hex, names) = color_names(&img.palette, &paints);
+    Ok(pdf::write(&drawing, &img.palette, &hex, &names, options))
+}
+
+/// Flatten an image into areas of solid color.
+/// Besides the encoded image, this reports how many regions it has and how big the smallest one is.
+#[wasm_bindgen]
+pub fn img_to_flat(input: Vec<u8>, options: &PbnOptions) -> Result<FlatImage, JsError> {
+    console_error_panic_hook::set_once();
+    check_options(options)?;
+    let img = flatten(&input, options)?;
+
+    // Count the regions at the working size, the same size min_area is measured in
+    let map = RegionMap::new(&img);
+    let region_count = map.areas().iter().filter(|a| a.pixels > 0).count() as u32;
+    let min_region_area = map.areas().iter().map(|a| a.pixels).filter(|p| *p > 0).min().unwrap_or(0);
+    println!("Regions: {region_count}, smallest: {min_region_area} pixels");
+
+    // Scale up the image
+    let img = canvas::scale(&img, options.scale);
 
     // Convert the image to a vector of bytes
-    let flat_img = imgutil::image_to_vec(&img_rgb, image::ImageFormat::Png);
+    let data = imgutil::image_to_vec(&img.to_rgb(), options.output_format.image_format())?;
+    Ok(FlatImage {
+        data,
+        region_count,
+        min_region_area,
+    })
+}
+
+/// Pick a palette size for an image instead of guessing `k`.
+/// The report has the chosen `k` and the error for every size tried, so the UI can explain the choice.
+#[wasm_bindgen]
+pub fn choose_palette_size(
+    input: Vec<u8>,
+    min_k: u32,
+    max_k: u32,
+    objective: PaletteObjective,
+    target_delta_e: f64,
+    options: &PbnOptions,
+) -> Result<PaletteSizeReport, JsError> {
+    console_error_panic_hook::set_once();
+    check_options(options)?;
+    check_color_range(min_k, max_k)?;
+
+    // Open, shrink and smooth the image the same way as img_to_flat
+    let img_rgb = canvas::shrink(imgutil::vec_to_rgb(&input)?, options.max_size);
+    let img_rgb = filter::smooth(img_rgb, options.smoothing, options.smoothing_radius, options.smoothing_strength);
+
+    // Every size gets quantized, so work on a coarser histogram to keep this fast
+    let hist = histogram::coarsen(&histogram::histogram(&img_rgb), 5);
 
-    flat_img
+    let mut rng = kmeans::make_rng(options.seed);
+    Ok(palette_size::choose_k(
+        &hist,
+        min_k..=max_k,
+        objective,
+        target_delta_e,
+        options.quantizer.quantizer(options).as_ref(),
+        options.color_space,
+        &mut rng,
+    ))
 }
 
+/// Convert a flat image to SVG.
+/// With a fixed palette, labels are numbered in palette order and `names` holds the paint names.
+/// If base pigments are given, `recipes` holds how to mix each color from them.
 #[wasm_bindgen]
-pub fn flat_to_svg(input: Vec<u8>) -> SvgData {
+pub fn flat_to_svg(input: Vec<u8>, options: &PbnOptions) -> Result<SvgData, JsError> {
     console_error_panic_hook::set_once();
+    check_options(options)?;
+    let paints = parse_paints(&options.palette)?;
 
-    // Open the image
-    let img = imgutil::vec_to_image(&input).unwrap();
-    let img_rgb = img.to_rgb8();
+    // Open the image and number its colors, with the palette colors first
+    let img_rgb = imgutil::vec_to_rgb(&input)?;
+    let palette_colors = paints.iter().map(|p| p.color).collect::<Vec<_>>();
+    let img = indexed::IndexedImage::from_rgb(&img_rgb, &palette_colors)?;
 
-    // Convert the image to SVG
-    let (svg_data, colors) = svg::img_to_svg(&img_rgb);
+    Ok(puzzle_svg(&img, 1, options)?)
+}
 
-    // Return the SVG data
-    SvgData::new(svg_data, colors)
+/// Go straight from a photo to the SVG, palette and regions in one call.
+/// This traces the label map at its working size and scales the outlines, so the flat image
+/// never has to be scaled up, encoded and decoded. Set `includeFlat` to also get the flat image.
+#[wasm_bindgen]
+pub fn image_to_puzzle(input: Vec<u8>, options: &PbnOptions) -> Result<SvgData, JsError> {
+    console_error_panic_hook::set_once();
+    check_options(options)?;
+    let img = flatten(&input, options)?;
+
+    let mut data = puzzle_svg(&img, options.scale, options)?;
+    if options.include_flat {
+        let flat = canvas::scale(&img, options.scale).to_rgb();
+        data.flat = Some(imgutil::image_to_vec(&flat, options.output_format.image_format())?);
+    }
+    Ok(data)
+}
+
+/// Go straight from a photo to a printable PDF, with the outlines and numbers on the first page and
+/// the color legend and a preview on the second. Paper and margins come from the options.
+/// This is the native version of `image_to_pdf`, for use outside the browser.
+pub fn pdf_from_image(input: &[u8], options: &PbnOptions) -> Result<Vec<u8>, PbnError> {
+    check_options(options)?;
+    let img = flatten(input, options)?;
+    puzzle_pdf(&img, options.scale, options)
+}
+
+/// Go straight from a photo to a printable PDF, see `pdf_from_image`
+#[wasm_bindgen]
+pub fn image_to_pdf(input: Vec<u8>, options: &PbnOptions) -> Result<Vec<u8>, JsError> {
+    console_error_panic_hook::set_once();
+    Ok(pdf_from_image(&input, options)?)
+}
+
+/// Convert a flat image to a printable PDF, numbering colors the same way as `flat_to_svg`
+#[wasm_bindgen]
+pub fn flat_to_pdf(input: Vec<u8>, options: &PbnOptions) -> Result<Vec<u8>, JsError> {
+    console_error_panic_hook::set_once();
+    check_options(options)?;
+    let paints = parse_paints(&options.palette)?;
+    let img_rgb = imgutil::vec_to_rgb(&input)?;
+    let palette_colors = paints.iter().map(|p| p.color).collect::<Vec<_>>();
+    let img = indexed::IndexedImage::from_rgb(&img_rgb, &palette_colors)?;
+
+    Ok(puzzle_pdf(&img, 1, options)?)
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use image::Rgb;
 
     #[test]
     fn all() {
@@ -102,22 +498,25 @@ mod tests {
         let flat_file_name = "./test/tree_paint.png";
         let svg_file_name = "./test/tree_paint.svg";
         let color_file_name = "./test/tree_colors.txt";
-        let k = 10;
-        let min_area = 30;
+        let options = PbnOptions {
+            k: 10,
+            min_area: 30,
+            ..PbnOptions::default()
+        };
 
         // Open image
         let img = image::open(file_name).unwrap();
         let img_rgb = img.to_rgb8();
-        let buffer = imgutil::image_to_vec(&img_rgb, image::ImageFormat::Png);
+        let buffer = imgutil::image_to_vec(&img_rgb, image::ImageFormat::Png).unwrap();
 
-        let out = img_to_flat(buffer, k, min_area);
+        let out = img_to_flat(buffer, &options).unwrap().data;
         
         // Write image to file
         let img = imgutil::vec_to_image(&out).unwrap();
         img.save(flat_file_name).unwrap();
 
         // Convert to SVG
-        let svg = flat_to_svg(out);
+        let svg = flat_to_svg(out, &options).unwrap();
         std::fs::write(svg_file_name, svg.svg).expect("Unable to write file");
         std::fs::write(color_file_name, svg.colors.join("\n")).expect("Unable to write file");
     }
@@ -127,15 +526,18 @@ mod tests {
         // Variables!
         let file_name = "./test/tree.jpg";
         let flat_file_name = "./test/tree_paint.png";
-        let k = 10;
-        let min_area = 30;
+        let options = PbnOptions {
+            k: 10,
+            min_area: 30,
+            ..PbnOptions::default()
+        };
 
         // Open image
         let img = image::open(file_name).unwrap();
         let img_rgb = img.to_rgb8();
-        let buffer = imgutil::image_to_vec(&img_rgb, image::ImageFormat::Png);
+        let buffer = imgutil::image_to_vec(&img_rgb, image::ImageFormat::Png).unwrap();
 
-        let out = img_to_flat(buffer, k, min_area);
+        let out = img_to_flat(buffer, &options).unwrap().data;
         
         // Write image to file
         let img = imgutil::vec_to_image(&out).unwrap();
@@ -147,14 +549,805 @@ mod tests {
         let file_name = "./test/clouds_flat.png";
         let svg_file_name = "./test/tree_paint.svg";
         let color_file_name = "./test/tree_colors.txt";
+        let options = PbnOptions::default();
 
         let img = image::open(file_name).expect("Run test_flat_img first");
         let img_rgb = img.to_rgb8();
-        let buffer = imgutil::image_to_vec(&img_rgb, image::ImageFormat::Png);
+        let buffer = imgutil::image_to_vec(&img_rgb, image::ImageFormat::Png).unwrap();
 
-        let svg = flat_to_svg(buffer);
+        let svg = flat_to_svg(buffer, &options).unwrap();
 
         std::fs::write(svg_file_name, svg.svg).expect("Unable to write file");
         std::fs::write(color_file_name, svg.colors.join("\n")).expect("Unable to write file");
     }
+
+    #[test]
+    fn test_color_space_round_trip() {
+        let spaces = [ColorSpace::Srgb, ColorSpace::LinearRgb, ColorSpace::Lab, ColorSpace::Oklab];
+        let colors = [Rgb([0, 0, 0]), Rgb([255, 255, 255]), Rgb([224, 172, 105]), Rgb([12, 200, 47])];
+        for space in spaces {
+            for color in colors.iter() {
+                assert_eq!(space.to_rgb(&space.from_rgb(color)), *color, "{space:?}");
+            }
+        }
+    }
+
+    /// Small synthetic gradient image so tests stay fast
+    fn gradient_png() -> Vec<u8> {
+        let img = image::RgbImage::from_fn(64, 48, |x, y| {
+            Rgb([(x * 4) as u8, (y * 5) as u8, ((x + y) * 2) as u8])
+        });
+        imgutil::image_to_vec(&img, image::ImageFormat::Png).unwrap()
+    }
+
+    #[test]
+    fn test_seed_is_deterministic() {
+        let buffer = gradient_png();
+        let options = PbnOptions {
+            k: 6,
+            min_area: 10,
+            seed: Some(42),
+            ..PbnOptions::default()
+        };
+
+        let a = img_to_flat(buffer.clone(), &options).unwrap().data;
+        let b = img_to_flat(buffer, &options).unwrap().data;
+        assert_eq!(a, b);
+    }
+
+    #[test]
+    fn test_quantizers() {
+        let img = imgutil::vec_to_image(&gradient_png()).unwrap().to_rgb8();
+        let hist = histogram::histogram(&img);
+        let kinds = [QuantizerKind::KMeans, QuantizerKind::MedianCut, QuantizerKind::Octree, QuantizerKind::Wu];
+        for kind in kinds {
+            let mut rng = kmeans::make_rng(Some(1));
+            let palette = kind.quantizer(&PbnOptions::default()).quantize(&hist, 8, &mut rng);
+            assert!(!palette.is_empty() && palette.len() <= 8, "{kind:?} gave {} colors", palette.len());
+        }
+
+        // Four tight, well separated clusters should each get a color close to their center
+        let centers = [Rgb([30, 30, 200]), Rgb([220, 40, 40]), Rgb([40, 200, 60]), Rgb([230, 220, 50])];
+        let mut hist = Vec::new();
+        for center in centers.iter() {
+            for (i, d) in [-6i16, -3, 0, 3, 6].iter().enumerate() {
+                let shift = |c: u8| (c as i16 + d) as u8;
+                hist.push((Rgb([shift(center[0]), center[1], shift(center[2])]), 10 + i as u32));
+                hist.push((Rgb([center[0], shift(center[1]), center[2]]), 10 + i as u32));
+            }
+        }
+        for kind in kinds {
+            let mut rng = kmeans::make_rng(Some(1));
+            let palette = kind.quantizer(&PbnOptions::default()).quantize(&hist, 4, &mut rng);
+            for center in centers.iter() {
+                let lab = ColorSpace::Lab.from_rgb(center);
+                let closest = palette
+                    .iter()
+                    .map(|c| color::distance(&lab, &ColorSpace::Lab.from_rgb(c)))
+                    .fold(f64::MAX, f64::min);
+                assert!(closest < 3.0, "{kind:?} missed {center:?} by {closest}: {palette:?}");
+            }
+        }
+    }
+
+    #[test]
+    fn test_choose_palette_size() {
+        let report = choose_palette_size(
+            gradient_png(),
+            2,
+            12,
+            PaletteObjective::Elbow,
+            0.0,
+            &PbnOptions {
+                quantizer: QuantizerKind::Wu,
+                seed: Some(1),
+                ..PbnOptions::default()
+            },
+        )
+        .unwrap();
+        assert!((2..=12).contains(&report.k()));
+        assert_eq!(report.stats().len(), 11);
+
+        // More colors should never be much worse
+        let stats = report.stats();
+        assert!(stats[stats.len() - 1].mean_delta_e <= stats[0].mean_delta_e);
+
+        // A target maximum ΔE picks the first size whose worst color is within it
+        let target = stats[5].max_delta_e;
+        let report = choose_palette_size(
+            gradient_png(),
+            2,
+            12,
+            PaletteObjective::MaxDeltaE,
+            target,
+            &PbnOptions {
+                quantizer: QuantizerKind::Wu,
+                ..PbnOptions::default()
+            },
+        )
+        .unwrap();
+        let first = stats.iter().find(|s| s.max_delta_e <= target).unwrap();
+        assert_eq!(report.k(), first.k);
+    }
+
+    #[test]
+    fn test_fixed_palette() {
+        let palette = ["Titanium White=#F4F4F0", "Mars Black: #1B1B1B", "crimson", "#3050C0", "gold"]
+            .map(String::from)
+            .to_vec();
+        let options = PbnOptions {
+            k: 3,
+            min_area: 10,
+            palette,
+            ..PbnOptions::default()
+        };
+        let flat = img_to_flat(gradient_png(), &options).unwrap().data;
+        let svg = flat_to_svg(flat, &options).unwrap();
+
+        // Only three paints are used, and every one is named after its paint
+        assert!(!svg.names.is_empty() && svg.names.len() <= 3);
+        for name in svg.names.iter() {
+            assert!(!name.starts_with('#') || name == "#3050C0", "{name}");
+        }
+    }
+
+    #[test]
+    fn test_mixing_recipes() {
+        let pigments = [
+            "Titanium White=#F5F5F2",
+            "Ivory Black=#1E1E1E",
+            "Cadmium Yellow=#FFD500",
+            "Ultramarine=#1C2F8C",
+            "Cadmium Red=#D2201E",
+        ]
+        .map(|p| palette::parse_paint(p).unwrap());
+        let targets = [Rgb([245, 245, 242]), Rgb([130, 130, 128]), Rgb([60, 110, 60])];
+        let recipes = mixing::recipes(&targets, &pigments);
+
+        // A pigment on its own mixes to itself, and grey comes from black and white
+        assert_eq!(recipes[0].describe(), "1 part Titanium White");
+        assert!(recipes[0].delta_e < 1.0);
+        assert!(recipes[1].pigments.contains(&"Ivory Black".to_string()));
+        assert!(recipes[1].pigments.contains(&"Titanium White".to_string()));
+
+        // Green has to come from yellow and blue, not averaging in RGB
+        assert!(recipes[2].pigments.contains(&"Cadmium Yellow".to_string()));
+        assert!(recipes[2].pigments.contains(&"Ultramarine".to_string()));
+    }
+
+    #[test]
+    fn test_errors() {
+        // Garbage bytes and bad paints are reported, not panicked on
+        assert!(matches!(imgutil::vec_to_rgb(&[1, 2, 3]), Err(PbnError::Decode(_))));
+        assert!(matches!(
+            parse_paints(&["not a paint".to_string()]),
+            Err(PbnError::InvalidPaint(_))
+        ));
+        assert!(matches!(check_color_count(0), Err(PbnError::InvalidColorCount(0))));
+        assert!(matches!(check_color_range(2, 1_000_000), Err(PbnError::InvalidColorCount(1_000_000))));
+        assert!(matches!(check_color_range(12, 2), Err(PbnError::InvalidColorRange(12, 2))));
+
+        // Settings out of range are caught before any work is done
+        let bad = [
+            PbnOptions { scale: 0, ..PbnOptions::default() },
+            PbnOptions { max_size: 0, ..PbnOptions::default() },
+            PbnOptions { stroke_width: -1.0, ..PbnOptions::default() },
+            PbnOptions { curve_tolerance: f64::NAN, ..PbnOptions::default() },
+            PbnOptions { min_font_size: 30.0, max_font_size: 10.0, ..PbnOptions::default() },
+            PbnOptions { min_regions: 50, max_regions: 10, ..PbnOptions::default() },
+            PbnOptions { max_iterations: 0, ..PbnOptions::default() },
+            PbnOptions { smoothing_radius: u32::MAX, ..PbnOptions::default() },
+            PbnOptions { min_width: 5000, ..PbnOptions::default() },
+        ];
+        for options in bad.iter() {
+            assert!(matches!(check_options(options), Err(PbnError::InvalidOption { .. })), "{options:?}");
+        }
+        assert!(check_options(&PbnOptions::default()).is_ok());
+        let pigments = vec!["red".to_string(); 100];
+        let many = PbnOptions { pigments, ..PbnOptions::default() };
+        assert!(matches!(check_options(&many), Err(PbnError::TooManyPigments(100))));
+
+        // A photo that isn't flat has too many colors to number
+        let photo = image::RgbImage::from_fn(257, 256, |x, y| {
+            let i = y * 257 + x;
+            Rgb([(i >> 16) as u8, (i >> 8) as u8, i as u8])
+        });
+        assert!(matches!(IndexedImage::from_rgb(&photo, &[]), Err(PbnError::TooManyColors(65792))));
+        assert!(matches!(pdf_from_image(&[], &bad[0]), Err(PbnError::InvalidOption { name: "scale", .. })));
+
+        // A solid color image has fewer unique colors than k
+        let img = image::RgbImage::from_pixel(20, 20, Rgb([40, 90, 200]));
+        let buffer = imgutil::image_to_vec(&img, image::ImageFormat::Png).unwrap();
+        let options = PbnOptions {
+            min_area: 5,
+            seed: Some(1),
+            ..PbnOptions::default()
+        };
+        let flat = img_to_flat(buffer, &options).unwrap().data;
+        let svg = flat_to_svg(flat, &options).unwrap();
+        assert_eq!(svg.colors, vec!["#285AC8".to_string()]);
+    }
+
+    #[test]
+    fn test_options() {
+        let options = PbnOptions {
+            k: 4,
+            max_size: 32,
+            min_width: 3,
+            scale: 2,
+            output_format: FlatFormat::Bmp,
+            quantizer: QuantizerKind::MedianCut,
+            stroke_color: "#123\"456".to_string(),
+            min_font_size: 7.5,
+            max_font_size: 7.5,
+            ..PbnOptions::default()
+        };
+        let flat = img_to_flat(gradient_png(), &options).unwrap().data;
+
+        // 64x48 shrinks to 32x24, then doubles
+        assert!(flat.starts_with(b"BM"));
+        let img = imgutil::vec_to_rgb(&flat).unwrap();
+        assert_eq!(img.dimensions(), (64, 48));
+
+        let svg = flat_to_svg(flat, &options).unwrap();
+        assert!(svg.svg.contains("stroke=\"#123&quot;456\""));
+        assert!(svg.svg.contains("font-size=\"7.5\""));
+    }
+
+    #[test]
+    fn test_image_to_puzzle() {
+        let options = PbnOptions {
+            k: 5,
+            min_area: 10,
+            seed: Some(3),
+            include_flat: true,
+            ..PbnOptions::default()
+        };
+        let puzzle = image_to_puzzle(gradient_png(), &options).unwrap();
+
+        // One region per shape, and the areas cover the whole scaled image
+        assert_eq!(puzzle.regions.len(), puzzle.svg.matches("id=\"shape-").count());
+        let area: u32 = puzzle.regions.iter().map(|r| r.area).sum();
+        assert_eq!(area, 64 * 48 * 16);
+        assert!(puzzle.regions.iter().all(|r| r.color as usize <= puzzle.colors.len()));
+
+        // The same as going through the flat image, minus the round trip
+        let flat = puzzle.flat.unwrap();
+        assert_eq!(flat, img_to_flat(gradient_png(), &options).unwrap().data);
+        let svg = flat_to_svg(flat, &options).unwrap();
+        let shapes = |svg: &str| svg.lines().filter(|l| l.contains("id=\"shape-")).map(String::from).collect::<Vec<_>>();
+        assert_eq!(shapes(&svg.svg), shapes(&puzzle.svg));
+        assert_eq!(svg.colors, puzzle.colors);
+    }
+
+    #[test]
+    fn test_denoise() {
+        // Checkerboard-ish noise over two big halves
+        let palette = vec![Rgb([0, 0, 0]), Rgb([255, 255, 255]), Rgb([200, 30, 30])];
+        let img = indexed::IndexedImage::from_fn(40, 30, palette, |x, y| {
+            if (x * 7 + y * 13) % 11 == 0 {
+                2
+            } else if x < 20 {
+                0
+            } else {
+                1
+            }
+        });
+        let source = img.to_rgb();
+        let strategies = [
+            MergeStrategy::LongestBorder,
+            MergeStrategy::NearestColor,
+            MergeStrategy::Weighted,
+            MergeStrategy::MinError,
+        ];
+        for strategy in strategies {
+            let img = canvas::denoise(img.clone(), &source, 12, strategy);
+
+            // Every area left is big enough, and the noise color is gone
+            let map = regions::RegionMap::new(&img);
+            assert!(map.areas().iter().all(|a| a.pixels >= 12 && a.color != 2), "{strategy:?}");
+
+            // Neighbors agree on how much border they share
+            for (id, area) in map.areas().iter().enumerate() {
+                for (n, len) in area.neighbors.iter() {
+                    assert_eq!(map.area(*n).neighbors[&(id as u32)], *len);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_merge_strategy() {
+        // A light grey sliver on the dark side, sharing more border with the dark area
+        let palette = vec![Rgb([10, 10, 10]), Rgb([240, 240, 240]), Rgb([200, 200, 200])];
+        let img = indexed::IndexedImage::from_fn(30, 20, palette, |x, y| match (x, y) {
+            (14, 5..10) => 2,
+            (0..15, _) => 0,
+            _ => 1,
+        });
+        let source = img.to_rgb();
+        let sliver = |strategy| canvas::denoise(img.clone(), &source, 10, strategy).get(14, 7);
+
+        assert_eq!(sliver(MergeStrategy::LongestBorder), 0);
+        assert_eq!(sliver(MergeStrategy::NearestColor), 1);
+        assert_eq!(sliver(MergeStrategy::MinError), 1);
+    }
+
+    #[test]
+    fn test_remove_thin() {
+        // Two halves crossed by a one pixel line and a two pixel line
+        let palette = vec![Rgb([0, 0, 0]), Rgb([255, 255, 255]), Rgb([200, 30, 30]), Rgb([30, 30, 200])];
+        let img = indexed::IndexedImage::from_fn(40, 30, palette, |x, y| match (x, y) {
+            (_, 10) => 2,
+            (_, 20..22) => 3,
+            (0..20, _) => 0,
+            _ => 1,
+        });
+        let img = canvas::remove_thin(img, 3);
+
+        // The lines are gone, and the halves are untouched
+        let map = regions::RegionMap::new(&img);
+        assert!(map.areas().iter().all(|a| a.color < 2), "{:?}", map.areas());
+        assert_eq!(img.get(5, 5), 0);
+        assert_eq!(img.get(35, 25), 1);
+    }
+
+    #[test]
+    fn test_smoothing() {
+        // A noisy two tone image
+        let img = image::RgbImage::from_fn(32, 24, |x, y| {
+            let base = if x < 16 { 60 } else { 190 };
+            let noise = ((x * 37 + y * 91) % 31) as u8;
+            Rgb([base + noise, base + noise / 2, base])
+        });
+        // Variance of the red channel inside the left half
+        let spread = |img: &image::RgbImage| {
+            let values = (0..24).flat_map(|y| (0..14).map(move |x| (x, y))).map(|(x, y)| img.get_pixel(x, y)[0] as f64);
+            let mean = values.clone().sum::<f64>() / (14.0 * 24.0);
+            values.map(|v| (v - mean).powi(2)).sum::<f64>()
+        };
+
+        let filters = [Smoothing::Bilateral, Smoothing::MeanShift, Smoothing::Kuwahara, Smoothing::Median];
+        for filter in filters {
+            let out = filter::smooth(img.clone(), filter, 2, 40.0);
+            assert_eq!(out.dimensions(), img.dimensions());
+
+            // Less noise, but the edge between the halves stays sharp
+            assert!(spread(&out) < spread(&img) / 2.0, "{filter:?}");
+            assert!(out.get_pixel(14, 12)[2] < 100 && out.get_pixel(17, 12)[2] > 150, "{filter:?}");
+        }
+    }
+
+    #[test]
+    fn test_target_regions() {
+        let options = PbnOptions {
+            k: 8,
+            min_area: 1,
+            seed: Some(5),
+            ..PbnOptions::default()
+        };
+        let free = img_to_flat(gradient_png(), &options).unwrap();
+
+        // Merging stops inside the range, and the regions that are left got bigger
+        let target = img_to_flat(
+            gradient_png(),
+            &PbnOptions {
+                min_regions: 3,
+                max_regions: 5,
+                ..options.clone()
+            },
+        )
+        .unwrap();
+        assert!(free.region_count > 5);
+        assert!((3..=5).contains(&target.region_count), "{}", target.region_count);
+        assert!(target.min_region_area >= free.min_region_area);
+
+        // Merging the most similar areas first also lands in the range
+        let similar = img_to_flat(
+            gradient_png(),
+            &PbnOptions {
+                min_regions: 3,
+                max_regions: 5,
+                merge_order: MergeOrder::MostSimilar,
+                ..options.clone()
+            },
+        )
+        .unwrap();
+        assert!((3..=5).contains(&similar.region_count), "{}", similar.region_count);
+    }
+
+    #[test]
+    fn test_difficulty() {
+        // Harder presets have more colors and more, smaller regions
+        let levels = [Difficulty::Beginner, Difficulty::Intermediate, Difficulty::Expert, Difficulty::Master]
+            .map(PbnOptions::for_difficulty);
+        for pair in levels.windows(2) {
+            assert!(pair[0].k < pair[1].k);
+            assert!(pair[0].max_regions < pair[1].max_regions);
+            assert!(pair[0].min_area > pair[1].min_area);
+            assert!(pair[0].min_width > pair[1].min_width);
+            assert!(pair[0].min_font_size > pair[1].min_font_size);
+        }
+
+        // A beginner puzzle of a small image stays within its region budget
+        let flat = img_to_flat(gradient_png(), &PbnOptions { seed: Some(2), ..levels[0].clone() }).unwrap();
+        assert!(flat.region_count <= levels[0].max_regions);
+
+        // A detailed photo lands inside the beginner range, not just under its ceiling
+        let bytes = std::fs::read("./test/tree.jpg").unwrap();
+        let flat = img_to_flat(bytes, &PbnOptions { seed: Some(1), ..levels[0].clone() }).unwrap();
+        assert!((levels[0].min_regions..=levels[0].max_regions).contains(&flat.region_count));
+    }
+
+    #[test]
+    fn test_trace() {
+        // A ring with a hole, a diagonal touch and an island
+        let palette = vec![Rgb([0, 0, 0]), Rgb([255, 255, 255]), Rgb([200, 30, 30])];
+        let img = indexed::IndexedImage::from_fn(12, 10, palette, |x, y| match (x, y) {
+            (2..=6, 2..=6) if (x, y) != (4, 4) => 1,
+            (7, 7) => 1,
+            (9, 1) => 2,
+            _ => 0,
+        });
+        let map = regions::RegionMap::new(&img);
+        let boundaries = trace(&map);
+
+        // Every edge is walked once by each area beside it, in opposite directions
+        let mut uses = vec![Vec::new(); boundaries.edges.len()];
+        for (id, rings) in boundaries.rings.iter().enumerate() {
+            for (edge, reversed) in rings.iter().flatten() {
+                let e = &boundaries.edges[*edge];
+                assert_eq!(if *reversed { e.right } else { e.left }, id as u32);
+                uses[*edge].push(*reversed);
+            }
+        }
+        for (edge, used) in boundaries.edges.iter().zip(uses) {
+            let expected = if edge.right == OUTSIDE { vec![false] } else { vec![false, true] };
+            let mut used = used;
+            used.sort();
+            assert_eq!(used, expected);
+        }
+
+        // The ring around the hole pixel is a hole, and the diagonal pixel is its own area
+        let ring_area = map.label(2, 2) as usize;
+        assert_eq!(boundaries.rings[ring_area].len(), 2);
+        assert_ne!(map.label(7, 7), map.label(6, 6));
+
+        // Rings are closed and follow the cracks between pixels
+        for ring in boundaries.rings.iter().flatten() {
+            let points = boundaries.ring_points(ring);
+            for (a, b) in points.iter().zip(points.iter().cycle().skip(1)) {
+                assert_eq!(a.0.abs_diff(b.0) + a.1.abs_diff(b.1), 1);
+            }
+        }
+    }
+
+    #[test]
+    fn test_curves() {
+        // A disc on a background, which is a staircase at the pixel level
+        let palette = vec![Rgb([0, 0, 0]), Rgb([255, 255, 255])];
+        let img = indexed::IndexedImage::from_fn(40, 40, palette, |x, y| {
+            ((x as i32 - 20).pow(2) + (y as i32 - 20).pow(2) < 15 * 15) as u16
+        });
+        let path = |svg: &str, id: &str| {
+            let line = svg.lines().find(|l| l.contains(&format!("id=\"{id}\""))).unwrap();
+            line.split(" d=\"").nth(1).unwrap().split('"').next().unwrap().to_string()
+        };
+        let points = |d: &str| {
+            let nums = d.split(' ').filter_map(|n| n.trim_start_matches('M').parse::<f64>().ok()).collect::<Vec<_>>();
+            nums.chunks(2).map(|p| (p[0].to_string(), p[1].to_string())).collect::<std::collections::BTreeSet<_>>()
+        };
+
+        // No tolerance keeps the pixel edges
+        let straight = PbnOptions {
+            curve_tolerance: 0.0,
+            ..PbnOptions::default()
+        };
+        let (svg, _) = svg::img_to_svg(&img, 4, &straight);
+        assert!(!svg.contains(" C "));
+
+        // Smoothing turns the disc into far fewer curves
+        let (curved, _) = svg::img_to_svg(&img, 4, &PbnOptions::default());
+        let (disc, background) = (path(&curved, "shape-1"), path(&curved, "shape-0"));
+        assert!(disc.contains(" C "));
+        let segments = |d: &str| d.matches(" C ").count() + d.matches(" L ").count();
+        assert!(segments(&disc) * 2 < segments(&path(&svg, "shape-1")));
+
+        // The hole in the background is the same outline as the disc, so they stay watertight
+        assert!(points(&disc).is_subset(&points(&background)));
+    }
+
+    #[test]
+    fn test_thin_areas() {
+        // A one pixel wide area inside the background, and one running along a third area, both thinner
+        // than the curve tolerance once scaled
+        let palette = vec![Rgb([0, 0, 0]), Rgb([255, 255, 255]), Rgb([200, 30, 30])];
+        let img = indexed::IndexedImage::from_fn(20, 30, palette, |x, y| match (x, y) {
+            (4, 4..=23) => 1,
+            (12, 4..=23) => 1,
+            (13..=16, 4..=23) => 2,
+            _ => 0,
+        });
+        let drawing = svg::draw(&img, 4, &PbnOptions::default());
+        assert_eq!(drawing.areas.len(), 4);
+
+        // Every ring keeps its area, holes included, with the outer ring counterclockwise on screen
+        for rings in drawing.areas.iter() {
+            assert!(rings[0].area() < 0.0);
+            for hole in rings.iter().skip(1) {
+                assert!(hole.area() > 0.0);
+            }
+        }
+    }
+
+    #[test]
+    fn test_label_placement() {
+        // A C shape, whose vertex average is in its mouth, and a thick ring around a hole
+        let palette = vec![Rgb([0, 0, 0]), Rgb([255, 255, 255]), Rgb([200, 30, 30])];
+        let img = indexed::IndexedImage::from_fn(60, 30, palette, |x, y| match (x, y) {
+            (2..=25, 2..=27) if !(8..=25).contains(&x) || !(8..=21).contains(&y) => 1,
+            (32..=57, 2..=27) if !(40..=49).contains(&x) || !(10..=19).contains(&y) => 2,
+            _ => 0,
+        });
+        let map = regions::RegionMap::new(&img);
+        let (_, regions) = svg::img_to_svg(&img, 2, &PbnOptions::default());
+
+        // Every label is inside its own area, away from the border
+        for region in regions.iter() {
+            let (x, y) = ((region.label_x / 2.0) as u32, (region.label_y / 2.0) as u32);
+            assert_eq!(map.label(x, y), region.id);
+            assert!(region.label_radius >= 1.0);
+        }
+
+        // The ring's 8 pixel sides fit a circle of radius a little over 4 pixels in their corners, and the C's label is in its spine
+        let ring = regions.iter().find(|r| r.color == 3).unwrap();
+        assert!(ring.label_radius > 8.0 && ring.label_radius < 10.0);
+        let c = regions.iter().find(|r| r.color == 2).unwrap();
+        assert!(c.label_x / 2.0 < 8.0);
+    }
+
+    #[test]
+    fn test_label_sizes() {
+        // A long stripe, a big block and a small square
+        let palette = vec![Rgb([0, 0, 0]), Rgb([255, 255, 255]), Rgb([200, 30, 30])];
+        let img = indexed::IndexedImage::from_fn(200, 60, palette, |x, y| match (x, y) {
+            (_, 0..=9) => 1,
+            (180..=183, 40..=43) => 2,
+            _ => 0,
+        });
+        let options = PbnOptions::default();
+        let (svg, regions) = svg::img_to_svg(&img, 4, &options);
+        let labels = |id: u32| svg.matches(&format!("id=\"label-{id}-")).count();
+
+        // Sizes follow the room each area has, within the limits
+        assert!(regions.iter().all(|r| r.font_size >= options.min_font_size && r.font_size <= options.max_font_size));
+        let (stripe, block, square) = (&regions[0], &regions[1], &regions[2]);
+        assert!(square.font_size < block.font_size);
+        assert_eq!(block.font_size, options.max_font_size);
+
+        // The stripe and block are long enough for several evenly spaced labels, the square only has one
+        assert!(labels(stripe.id) >= 1 && labels(block.id) >= 1);
+        assert_eq!(labels(square.id), 0);
+        assert_eq!(svg.matches("id=\"label-").count(), 3 + labels(stripe.id) + labels(block.id));
+
+        // No extra labels when turned off
+        let (svg, _) = svg::img_to_svg(&img, 4, &PbnOptions { label_spacing: 0.0, ..options });
+        assert_eq!(svg.matches("<text").count(), 3);
+    }
+
+    #[test]
+    fn test_callouts() {
+        // Single pixel specks in a big area, too small for a number
+        let palette = vec![Rgb([0, 0, 0]), Rgb([255, 255, 255])];
+        let img = indexed::IndexedImage::from_fn(40, 30, palette.clone(), |x, y| {
+            ((x % 7 == 3 && y % 6 == 2) || (x, y) == (4, 2)) as u16
+        });
+        let options = PbnOptions {
+            callouts: true,
+            ..PbnOptions::default()
+        };
+        let (svg, regions) = svg::img_to_svg(&img, 4, &options);
+
+        // Every speck gets a callout with a leader line, and no two numbers overlap
+        let specks = regions.iter().filter(|r| r.color == 2).collect::<Vec<_>>();
+        assert!(specks.iter().all(|r| r.callout));
+        assert_eq!(svg.matches("<line id=\"leader-").count(), specks.len());
+        let boxes = regions
+            .iter()
+            .map(|r| {
+                let half = layout::text_width(r.color, r.font_size) / 2.0;
+                (r.label_x - half, r.label_y - r.font_size / 2.0, r.label_x + half, r.label_y + r.font_size / 2.0)
+            })
+            .collect::<Vec<_>>();
+        for (i, a) in boxes.iter().enumerate() {
+            for b in boxes.iter().skip(i + 1) {
+                assert!(a.2 <= b.0 || b.2 <= a.0 || a.3 <= b.1 || b.3 <= a.1, "{a:?} overlaps {b:?}");
+            }
+        }
+
+        // A strip of alternating pixels has no room anywhere, so its numbers go in the margin
+        let img = indexed::IndexedImage::from_fn(8, 1, palette, |x, _| (x % 2) as u16);
+        let (svg, regions) = svg::img_to_svg(&img, 4, &options);
+        assert!(svg.contains("viewBox=\"-40 -40 112 84\""));
+        assert!(regions.iter().all(|r| r.callout));
+
+        // Without callouts the numbers stay put
+        let (svg, _) = svg::img_to_svg(&img, 4, &PbnOptions::default());
+        assert!(!svg.contains("<line"));
+    }
+
+    #[test]
+    fn test_label_layout() {
+        // Thin stripes, whose numbers spill into each other at the minimum font size
+        let palette = vec![Rgb([0, 0, 0]), Rgb([255, 255, 255]), Rgb([200, 30, 30]), Rgb([30, 30, 200])];
+        let img = indexed::IndexedImage::from_fn(40, 30, palette, |x, y| match (x, y) {
+            (0..=23, 0..=1) => (x % 2 + 1) as u16,
+            (_, 0..=1) => 3,
+            _ => 0,
+        });
+        let boxes = |svg: &str| {
+            svg.lines()
+                .filter(|l| l.starts_with("<text"))
+                .map(|l| {
+                    let attr = |name: &str| {
+                        let v = l.split(&format!(" {name}=\"")).nth(1).unwrap().split('"').next().unwrap();
+                        v.parse::<f64>().unwrap()
+                    };
+                    let id = l.split("id=\"label-").nth(1).unwrap().split(['"', '-']).next().unwrap();
+                    let number = l.split('>').nth(1).unwrap().split('<').next().unwrap().parse::<u32>().unwrap();
+                    let (x, y, size) = (attr("x"), attr("y"), attr("font-size"));
+                    let half = layout::text_width(number, size) / 2.0;
+                    (id.parse::<u32>().unwrap(), (x - half, y - size / 2.0, x + half, y + size / 2.0))
+                })
+                .collect::<Vec<_>>()
+        };
+
+        for callouts in [false, true] {
+            let options = PbnOptions {
+                callouts,
+                ..PbnOptions::default()
+            };
+            let (svg, regions) = svg::img_to_svg(&img, 4, &options);
+            let data = SvgData::new(svg.clone(), Vec::new(), Vec::new(), Vec::new(), regions.clone());
+
+            // Labels of labeled areas never overlap any other label
+            let boxes = boxes(&svg);
+            for (i, (a_id, a)) in boxes.iter().enumerate() {
+                for (b_id, b) in boxes.iter().skip(i + 1) {
+                    let apart = a.2 <= b.0 || b.2 <= a.0 || a.3 <= b.1 || b.3 <= a.1;
+                    assert!(apart || !regions[*a_id as usize].labeled || !regions[*b_id as usize].labeled);
+                }
+            }
+            assert!(regions.iter().all(|r| r.font_size >= options.min_font_size));
+
+            // Some stripes can't fit a number without callouts, and with them every area is labeled
+            assert_eq!(data.unlabeled().is_empty(), callouts);
+        }
+    }
+
+    #[test]
+    fn test_nesting() {
+        // A ring inside the background, holding two areas side by side, one with an island of its own.
+        // A pixel touching the ring's corner only diagonally sits in the background, not the ring.
+        let palette = (0..5).map(|i| Rgb([i * 50, 0, 0])).collect::<Vec<_>>();
+        let img = indexed::IndexedImage::from_fn(16, 14, palette, |x, y| match (x, y) {
+            (3..=12, 2..=11) if !(5..=10).contains(&x) || !(4..=9).contains(&y) => 1,
+            (5..=7, 4..=9) if (x, y) == (6, 6) => 4,
+            (5..=7, 4..=9) => 2,
+            (8..=10, 4..=9) => 3,
+            (13, 12) => 2,
+            _ => 0,
+        });
+        let map = regions::RegionMap::new(&img);
+        let boundaries = trace(&map);
+        let id = |x, y| map.label(x, y);
+
+        // Outer rings go counterclockwise on screen and come first, holes go clockwise
+        for rings in boundaries.rings.iter() {
+            assert!(boundaries.ring_area(&rings[0]) < 0);
+            assert!(rings.iter().skip(1).all(|ring| boundaries.ring_area(ring) > 0));
+        }
+        assert_eq!(boundaries.rings[id(0, 0) as usize].len(), 2);
+        assert_eq!(boundaries.rings[id(3, 2) as usize].len(), 2);
+
+        // Each area knows the area it's in a hole of
+        assert_eq!(boundaries.parents[id(0, 0) as usize], OUTSIDE);
+        assert_eq!(boundaries.parents[id(3, 2) as usize], id(0, 0));
+        assert_eq!(boundaries.parents[id(5, 4) as usize], id(3, 2));
+        assert_eq!(boundaries.parents[id(8, 4) as usize], id(3, 2));
+        assert_eq!(boundaries.parents[id(6, 6) as usize], id(5, 4));
+        assert_eq!(boundaries.parents[id(13, 12) as usize], id(0, 0));
+        let mut children = boundaries.children(id(3, 2));
+        children.sort();
+        assert_eq!(children, vec![id(5, 4), id(8, 4)]);
+
+        // The SVG fills with the nonzero rule and passes the tree on
+        let (svg, regions) = svg::img_to_svg(&img, 2, &PbnOptions::default());
+        assert!(svg.contains("fill-rule=\"nonzero\"") && !svg.contains("evenodd"));
+        assert_eq!(regions[id(6, 6) as usize].parent, Some(id(5, 4)));
+        assert_eq!(regions[id(0, 0) as usize].parent, None);
+    }
+
+    #[test]
+    fn test_pdf() {
+        let options = PbnOptions {
+            k: 5,
+            seed: Some(4),
+            ..PbnOptions::default()
+        };
+        let pdf = pdf_from_image(&gradient_png(), &options).unwrap();
+
+        // The same input gives the same bytes
+        assert_eq!(pdf, pdf_from_image(&gradient_png(), &options).unwrap());
+        let text = String::from_utf8(pdf).unwrap();
+        assert!(text.starts_with("%PDF-1.4\n") && text.ends_with("%%EOF\n"));
+
+        // Every cross reference points at its object, and every stream is as long as it says
+        let xref = text.rsplit("startxref\n").next().unwrap().lines().next().unwrap().parse::<usize>().unwrap();
+        let offsets = text[xref..].lines().skip(3).take_while(|l| l.ends_with(" n ")).collect::<Vec<_>>();
+        assert_eq!(offsets.len(), 8);
+        for (i, line) in offsets.iter().enumerate() {
+            let offset = line[..10].parse::<usize>().unwrap();
+            assert!(text[offset..].starts_with(&format!("{} 0 obj\n", i + 1)));
+        }
+        for part in text.split("/Length ").skip(1) {
+            let length = part.split(' ').next().unwrap().parse::<usize>().unwrap();
+            let content = part.split_once(">>\nstream\n").unwrap().1;
+            assert!(content[length..].starts_with("endstream"));
+        }
+
+        // A landscape image turns the paper sideways, and the legend has every color
+        assert!(text.contains("/MediaBox [0 0 841.89 595.28]"));
+        let colors = image_to_puzzle(gradient_png(), &options).unwrap().colors;
+        assert_eq!(text.matches(" 14 14 re B").count(), colors.len());
+        assert!(text.contains(&format!("({})", colors[0])));
+
+        // Other paper sizes
+        let letter = pdf_from_image(&gradient_png(), &PbnOptions { paper_size: PaperSize::Letter, ..options.clone() }).unwrap();
+        assert!(String::from_utf8(letter).unwrap().contains("/MediaBox [0 0 792 612]"));
+        let custom = PbnOptions {
+            paper_size: PaperSize::Custom,
+            paper_width: 100.0,
+            paper_height: 100.0,
+            ..options.clone()
+        };
+        let pdf = pdf_from_image(&gradient_png(), &custom).unwrap();
+        assert!(String::from_utf8(pdf).unwrap().contains("/MediaBox [0 0 283.46 283.46]"));
+
+        // An image far longer than it is wide still shrinks to something with outlines and numbers
+        let strip = image::RgbImage::from_fn(2, 3000, |_, y| Rgb([(y / 12) as u8, 80, 160]));
+        let strip = imgutil::image_to_vec(&strip, image::ImageFormat::Png).unwrap();
+        let pdf = String::from_utf8(pdf_from_image(&strip, &options).unwrap()).unwrap();
+        let outlines = &pdf[pdf.find("6 0 obj").unwrap()..pdf.find("7 0 obj").unwrap()];
+        assert!(outlines.contains(" l\n") && outlines.contains(") Tj"));
+
+        // The most colors allowed carry the legend on over more pages, with no column too narrow for an entry
+        let palette = (0..256).map(|i| Rgb([i as u8, (i * 7) as u8, 255 - i as u8])).collect::<Vec<_>>();
+        let img = indexed::IndexedImage::from_fn(16, 16, palette, |x, y| (y * 16 + x) as u16);
+        let pdf = String::from_utf8(puzzle_pdf(&img, 4, &options).unwrap()).unwrap();
+        let pages = pdf.split(" 0 obj\n").filter(|o| o.starts_with("<< /Type /Page ")).count();
+        assert!(pages > 2);
+        assert!(pdf.contains(&format!("/Count {pages}")));
+        assert_eq!(pdf.matches(" 14 14 re B").count(), 256);
+        let mut columns = pdf
+            .lines()
+            .filter(|l| l.ends_with(" 14 14 re B"))
+            .map(|l| l.split(' ').nth(8).unwrap().parse::<f64>().unwrap())
+            .collect::<Vec<_>>();
+        columns.dedup();
+        assert!(columns.windows(2).all(|w| w[1] < w[0] || w[1] - w[0] >= 90.0), "{columns:?}");
+        assert!(pdf.contains(&format!("({})", color::to_hex(&img.palette[255]))));
+
+        // Paper with no room to print on is an error, not a broken PDF
+        let bad = [
+            PbnOptions { paper_width: 0.0, ..custom.clone() },
+            PbnOptions { paper_height: f64::NAN, ..custom.clone() },
+            PbnOptions { paper_width: -50.0, ..custom.clone() },
+            PbnOptions { page_margin: 50.0, ..custom.clone() },
+            PbnOptions { page_margin: -1.0, ..options },
+        ];
+        for options in bad.iter() {
+            assert!(matches!(pdf_from_image(&gradient_png(), options), Err(PbnError::InvalidOption { .. })), "{options:?}");
+        }
+    }
 }
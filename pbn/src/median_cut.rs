@@ -0,0 +1,92 @@
+use image::Rgb;
+use rand_chacha::ChaCha8Rng;
+
+use crate::{histogram::HistogramEntry, quantize::Quantizer};
+
+/// Heckbert's median cut quantizer.
+/// Repeatedly splits the box with the widest channel range at its weighted median.
+pub struct MedianCut;
+
+impl Quantizer for MedianCut {
+    fn quantize(&self, hist: &[HistogramEntry], k: usize, _rng: &mut ChaCha8Rng) -> Vec<Rgb<u8>> {
+        println!("Running median cut with k={k} on {} unique colors...", hist.len());
+        let mut boxes = vec![hist.to_vec()];
+
+        while boxes.len() < k {
+            // Find the box with the widest range on any channel
+            let mut best = None;
+            let mut best_range = 0;
+            for (i, b) in boxes.iter().enumerate() {
+                if b.len() < 2 {
+                    continue;
+                }
+                let (_, range) = widest_channel(b);
+                if range > best_range {
+                    best_range = range;
+                    best = Some(i);
+                }
+            }
+
+            // Stop if no box can be split any further
+            let Some(i) = best else {
+                break;
+            };
+
+            // Sort along the widest channel and split at the weighted median
+            let mut b = boxes.swap_remove(i);
+            let (channel, _) = widest_channel(&b);
+            b.sort_by_key(|(c, _)| (c[channel], c.0));
+            let total = b.iter().map(|(_, n)| *n as u64).sum::<u64>();
+            let mut running = 0;
+            let mut split = 1;
+            for (j, (_, n)) in b.iter().enumerate() {
+                running += *n as u64;
+                if running * 2 >= total {
+                    split = j + 1;
+                    break;
+                }
+            }
+
+            // Both halves need at least one color
+            let split = split.clamp(1, b.len() - 1);
+            let upper = b.split_off(split);
+            boxes.push(b);
+            boxes.push(upper);
+        }
+
+        boxes.iter().map(|b| average(b)).collect()
+    }
+}
+
+/// Find the channel with the widest range in a box, returning the channel and its range
+fn widest_channel(b: &[HistogramEntry]) -> (usize, u8) {
+    let mut min = [u8::MAX; 3];
+    let mut max = [u8::MIN; 3];
+    for (c, _) in b.iter() {
+        for i in 0..3 {
+            min[i] = min[i].min(c[i]);
+            max[i] = max[i].max(c[i]);
+        }
+    }
+
+    let mut channel = 0;
+    for i in 1..3 {
+        if max[i] - min[i] > max[channel] - min[channel] {
+            channel = i;
+        }
+    }
+    (channel, max[channel] - min[channel])
+}
+
+/// Weighted average color of a box
+fn average(b: &[HistogramEntry]) -> Rgb<u8> {
+    let mut sum = [0u64; 3];
+    let mut total = 0u64;
+    for (c, n) in b.iter() {
+        for i in 0..3 {
+            sum[i] += c[i] as u64 * *n as u64;
+        }
+        total += *n as u64;
+    }
+    Rgb(sum.map(|s| ((s + total / 2) / total.max(1)) as u8))
+}
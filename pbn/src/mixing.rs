@@ -0,0 +1,163 @@
+use image::Rgb;
+use wasm_bindgen::prelude::*;
+
+use crate::{
+    color::{self, ColorSpace},
+    palette::Paint,
+};
+
+// The most pigments in one recipe, and the most parts of any one pigment
+const MAX_PIGMENTS: usize = 3;
+const MAX_PARTS: u32 = 6;
+
+/// The most base pigments recipes can be computed from. Every mix of up to MAX_PIGMENTS of them is
+/// tried, so the work grows with the cube of this.
+pub const MAX_BASE_PIGMENTS: usize = 16;
+
+// Keep reflectance away from 0 so K/S stays finite
+const MIN_REFLECTANCE: f64 = 0.001;
+
+/// How to mix a target color from a set of base pigments
+#[wasm_bindgen(getter_with_clone)]
+#[derive(Clone, Debug)]
+pub struct MixRecipe {
+    /// Hex code of the color being mixed
+    pub target: String,
+    /// Names of the pigments to mix
+    pub pigments: Vec<String>,
+    /// Parts of each pigment, in the same order as `pigments`
+    pub parts: Vec<u32>,
+    /// Hex code of the color the mix is predicted to have
+    pub predicted: String,
+    /// CIE76 ΔE between the target and the predicted color
+    #[wasm_bindgen(js_name = deltaE)]
+    pub delta_e: f64,
+}
+
+#[wasm_bindgen]
+impl MixRecipe {
+    /// Human readable recipe, for example "2 parts Titanium White + 1 part Ultramarine"
+    pub fn describe(&self) -> String {
+        self.pigments
+            .iter()
+            .zip(self.parts.iter())
+            .map(|(name, n)| format!("{n} part{} {name}", if *n == 1 { "" } else { "s" }))
+            .collect::<Vec<_>>()
+            .join(" + ")
+    }
+}
+
+/// Compute a mixing recipe for each color from the base pigments.
+/// Mixes are predicted with the single-constant Kubelka–Munk model: each pigment's
+/// absorption/scattering ratio K/S is averaged by parts and turned back into reflectance.
+pub fn recipes(colors: &[Rgb<u8>], pigments: &[Paint]) -> Vec<MixRecipe> {
+    println!("Computing mixing recipes for {} colors from {} pigments...", colors.len(), pigments.len());
+    let ks = pigments.iter().map(|p| reflectance(&p.color).map(k_over_s)).collect::<Vec<_>>();
+
+    // Predict every candidate mix once, as they don't depend on the target
+    let mixes = candidate_mixes(pigments.len())
+        .into_iter()
+        .map(|mix| {
+            let predicted = mix_color(&mix, &ks);
+            (mix, predicted, ColorSpace::Lab.from_rgb(&predicted))
+        })
+        .collect::<Vec<_>>();
+
+    colors
+        .iter()
+        .map(|target| {
+            let target_lab = ColorSpace::Lab.from_rgb(target);
+
+            // Try every candidate mix and keep the closest one
+            let mut best = None;
+            let mut best_delta_e = f64::MAX;
+            for (mix, predicted, lab) in mixes.iter() {
+                let delta_e = color::distance(&target_lab, lab);
+                if delta_e < best_delta_e {
+                    best_delta_e = delta_e;
+                    best = Some((mix, *predicted));
+                }
+            }
+
+            match best {
+                Some((mix, predicted)) => MixRecipe {
+                    target: color::to_hex(target),
+                    pigments: mix.iter().map(|(i, _)| pigments[*i].name.clone()).collect(),
+                    parts: mix.iter().map(|(_, n)| *n).collect(),
+                    predicted: color::to_hex(&predicted),
+                    delta_e: best_delta_e,
+                },
+                None => MixRecipe {
+                    target: color::to_hex(target),
+                    pigments: Vec::new(),
+                    parts: Vec::new(),
+                    predicted: String::new(),
+                    delta_e: f64::MAX,
+                },
+            }
+        })
+        .collect()
+}
+
+/// Every combination of up to MAX_PIGMENTS pigments with 1 to MAX_PARTS parts each,
+/// reduced so the parts have no common factor (2+2 is the same mix as 1+1)
+fn candidate_mixes(n: usize) -> Vec<Vec<(usize, u32)>> {
+    let mut mixes = Vec::new();
+    let mut stack = vec![Vec::<(usize, u32)>::new()];
+    while let Some(mix) = stack.pop() {
+        if !mix.is_empty() && mix.iter().map(|(_, p)| *p).fold(0, gcd) == 1 {
+            mixes.push(mix.clone());
+        }
+        if mix.len() == MAX_PIGMENTS {
+            continue;
+        }
+
+        // Only add pigments after the last one so each set is generated once
+        let start = mix.last().map_or(0, |(i, _)| i + 1);
+        for i in start..n {
+            for parts in 1..=MAX_PARTS {
+                let mut next = mix.clone();
+                next.push((i, parts));
+                stack.push(next);
+            }
+        }
+    }
+    mixes
+}
+
+fn gcd(a: u32, b: u32) -> u32 {
+    if b == 0 {
+        a
+    } else {
+        gcd(b, a % b)
+    }
+}
+
+/// Predict the color of a mix by averaging K/S by parts
+fn mix_color(mix: &[(usize, u32)], ks: &[[f64; 3]]) -> Rgb<u8> {
+    let total = mix.iter().map(|(_, n)| *n as f64).sum::<f64>();
+    let mut mixed = [0.0; 3];
+    for (i, n) in mix.iter() {
+        for c in 0..3 {
+            mixed[c] += ks[*i][c] * *n as f64 / total;
+        }
+    }
+    ColorSpace::LinearRgb.to_rgb(&mixed.map(from_k_over_s))
+}
+
+/// Treat each linear RGB channel as the reflectance in that band
+fn reflectance(rgb: &Rgb<u8>) -> [f64; 3] {
+    ColorSpace::LinearRgb
+        .from_rgb(rgb)
+        .map(|r| r.clamp(MIN_REFLECTANCE, 1.0))
+}
+
+/// Kubelka–Munk absorption/scattering ratio for a reflectance
+fn k_over_s(r: f64) -> f64 {
+    (1.0 - r) * (1.0 - r) / (2.0 * r)
+}
+
+/// Reflectance for a Kubelka–Munk absorption/scattering ratio
+fn from_k_over_s(ks: f64) -> f64 {
+    1.0 + ks - (ks * ks + 2.0 * ks).sqrt()
+}
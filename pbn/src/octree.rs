@@ -0,0 +1,118 @@
+use image::Rgb;
+use rand_chacha::ChaCha8Rng;
+
+use crate::{histogram::HistogramEntry, quantize::Quantizer};
+
+// One level per bit of each channel
+const MAX_DEPTH: usize = 8;
+
+/// Octree color quantizer.
+/// Builds a full octree of the histogram, then folds the least used
+/// deepest nodes into their parents until only `k` leaves remain.
+pub struct Octree;
+
+struct Node {
+    children: [Option<usize>; 8],
+    sum: [u64; 3],
+    count: u64,
+    leaf: bool,
+}
+
+impl Node {
+    fn new() -> Node {
+        Node {
+            children: [None; 8],
+            sum: [0; 3],
+            count: 0,
+            leaf: false,
+        }
+    }
+
+    fn add(&mut self, color: &Rgb<u8>, n: u64) {
+        for c in 0..3 {
+            self.sum[c] += color[c] as u64 * n;
+        }
+        self.count += n;
+    }
+}
+
+impl Quantizer for Octree {
+    fn quantize(&self, hist: &[HistogramEntry], k: usize, _rng: &mut ChaCha8Rng) -> Vec<Rgb<u8>> {
+        println!("Running octree with k={k} on {} unique colors...", hist.len());
+
+        // Nodes are stored in an arena, with the root at index 0
+        let mut nodes = vec![Node::new()];
+        let mut levels = vec![Vec::<usize>::new(); MAX_DEPTH];
+        let mut leaves = 0;
+
+        // Insert every color, accumulating sums down the path
+        for (color, n) in hist.iter() {
+            let n = *n as u64;
+            let mut curr = 0;
+            for (level, parents) in levels.iter_mut().enumerate() {
+                nodes[curr].add(color, n);
+
+                // Index the child by one bit of each channel
+                let shift = 7 - level;
+                let index = (((color[0] >> shift) & 1) << 2
+                    | ((color[1] >> shift) & 1) << 1
+                    | ((color[2] >> shift) & 1)) as usize;
+                curr = match nodes[curr].children[index] {
+                    Some(child) => child,
+                    None => {
+                        nodes.push(Node::new());
+                        let child = nodes.len() - 1;
+                        nodes[curr].children[index] = Some(child);
+                        parents.push(curr);
+                        child
+                    }
+                };
+            }
+
+            // The node at the maximum depth is always a leaf
+            let node = &mut nodes[curr];
+            node.add(color, n);
+            if !node.leaf {
+                node.leaf = true;
+                leaves += 1;
+            }
+        }
+
+        // Fold the deepest, least used nodes into their parents until we have k leaves
+        for level in (0..MAX_DEPTH).rev() {
+            if leaves <= k {
+                break;
+            }
+
+            // Nodes were pushed once per child, so dedup first
+            let mut reducible = std::mem::take(&mut levels[level]);
+            reducible.sort_unstable();
+            reducible.dedup();
+            reducible.sort_by_key(|i| (nodes[*i].count, *i));
+
+            for i in reducible {
+                if leaves <= k {
+                    break;
+                }
+                let children = nodes[i].children.iter().flatten().count();
+                nodes[i].children = [None; 8];
+                nodes[i].leaf = true;
+                leaves = leaves + 1 - children;
+            }
+        }
+
+        // Collect the average color of every reachable leaf
+        let mut palette = Vec::new();
+        let mut stack = vec![0];
+        while let Some(i) = stack.pop() {
+            let node = &nodes[i];
+            if node.leaf {
+                let count = node.count.max(1);
+                palette.push(Rgb(node.sum.map(|s| ((s + count / 2) / count) as u8)));
+                continue;
+            }
+            stack.extend(node.children.iter().rev().flatten());
+        }
+        palette
+    }
+}
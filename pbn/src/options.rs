@@ -0,0 +1,234 @@
+use image::ImageFormat;
+use wasm_bindgen::prelude::*;
+
+use crate::{canvas::{MergeOrder, MergeStrategy}, color::ColorSpace, filter::Smoothing, quantize::QuantizerKind};
+
+/// Lossless formats the flat image can be written in
+#[wasm_bindgen]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FlatFormat {
+    Png,
+    Bmp,
+    Tiff,
+}
+
+impl FlatFormat {
+    pub fn image_format(self) -> ImageFormat {
+        match self {
+            FlatFormat::Png => ImageFormat::Png,
+            FlatFormat::Bmp => ImageFormat::Bmp,
+            FlatFormat::Tiff => ImageFormat::Tiff,
+        }
+    }
+}
+
+/// Paper to print the PDF on
+#[wasm_bindgen]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PaperSize {
+    A4,
+    A3,
+    Letter,
+    /// `paperWidth` by `paperHeight`
+    Custom,
+}
+
+impl PaperSize {
+    /// Width and height in millimeters, portrait
+    pub fn size_mm(self, options: &PbnOptions) -> (f64, f64) {
+        match self {
+            PaperSize::A4 => (210.0, 297.0),
+            PaperSize::A3 => (297.0, 420.0),
+            PaperSize::Letter => (215.9, 279.4),
+            PaperSize::Custom => (options.paper_width, options.paper_height),
+        }
+    }
+}
+
+/// Named presets for how hard a puzzle is to paint
+#[wasm_bindgen]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Difficulty {
+    /// Few colors and a few hundred big, chunky regions with large labels
+    Beginner,
+    Intermediate,
+    Expert,
+    /// Many colors and thousands of small regions with fine detail
+    Master,
+}
+
+/// Every tunable setting of the pipeline.
+/// From JS, create one with `new PbnOptions()` and set the fields you want to change.
+#[wasm_bindgen(getter_with_clone)]
+#[derive(Clone, Debug)]
+pub struct PbnOptions {
+    /// Number of colors in the palette, or the most paints to use with a fixed palette
+    pub k: u32,
+    /// Areas with fewer pixels than this get merged into a neighbor
+    #[wasm_bindgen(js_name = minArea)]
+    pub min_area: u32,
+    /// Parts of areas narrower than this many pixels get absorbed by their neighbors, 0 to allow any width.
+    /// This is measured before scaling, so pick it from the print size, e.g. `maxSize` pixels over 30cm
+    /// means 20 pixels per cm, and a 3mm brush needs 6.
+    #[wasm_bindgen(js_name = minWidth)]
+    pub min_width: u32,
+    /// Keep merging areas until there are at most this many, 0 to turn this off
+    #[wasm_bindgen(js_name = maxRegions)]
+    pub max_regions: u32,
+    /// Stop merging for `maxRegions` rather than go below this many areas
+    #[wasm_bindgen(js_name = minRegions)]
+    pub min_regions: u32,
+    /// Which areas get merged first for `maxRegions`
+    #[wasm_bindgen(js_name = mergeOrder)]
+    pub merge_order: MergeOrder,
+    /// How small areas pick the neighbor they get merged into
+    #[wasm_bindgen(js_name = mergeStrategy)]
+    pub merge_strategy: MergeStrategy,
+    /// The image is shrunk so its longest side is at most this many pixels
+    #[wasm_bindgen(js_name = maxSize)]
+    pub max_size: u32,
+    /// Filter applied after shrinking to flatten noise before quantization
+    pub smoothing: Smoothing,
+    /// Window radius of the smoothing filter in pixels, at most 10
+    #[wasm_bindgen(js_name = smoothingRadius)]
+    pub smoothing_radius: u32,
+    /// How different two colors can be and still be smoothed together, in RGB units.
+    /// Only used by the bilateral and mean shift filters.
+    #[wasm_bindgen(js_name = smoothingStrength)]
+    pub smoothing_strength: f64,
+    /// How many times the flat image is scaled up, with nearest neighbor sampling
+    pub scale: u32,
+    /// The format of the flat image
+    #[wasm_bindgen(js_name = outputFormat)]
+    pub output_format: FlatFormat,
+    /// Also return the flat image from `image_to_puzzle`
+    #[wasm_bindgen(js_name = includeFlat)]
+    pub include_flat: bool,
+    /// The color space colors are compared and averaged in
+    #[wasm_bindgen(js_name = colorSpace)]
+    pub color_space: ColorSpace,
+    /// The quantization algorithm used to pick the palette
+    pub quantizer: QuantizerKind,
+    /// The most K-means iterations to run before giving up on convergence
+    #[wasm_bindgen(js_name = maxIterations)]
+    pub max_iterations: u32,
+    /// Makes the output reproducible; no seed gives different results each time
+    pub seed: Option<u64>,
+    /// Paints to map the image onto instead of computing a palette, see `palette::parse_paint`
+    pub palette: Vec<String>,
+    /// Base pigments to compute mixing recipes from
+    pub pigments: Vec<String>,
+    /// SVG outline color
+    #[wasm_bindgen(js_name = strokeColor)]
+    pub stroke_color: String,
+    /// SVG outline width
+    #[wasm_bindgen(js_name = strokeWidth)]
+    pub stroke_width: f64,
+    /// How far smoothed outlines may stray from the pixel edges, in SVG pixels, 0 for straight pixel edges
+    #[wasm_bindgen(js_name = curveTolerance)]
+    pub curve_tolerance: f64,
+    /// Smallest SVG label font size. Labels are sized to fit their area, but never smaller than this.
+    #[wasm_bindgen(js_name = minFontSize)]
+    pub min_font_size: f64,
+    /// Largest SVG label font size
+    #[wasm_bindgen(js_name = maxFontSize)]
+    pub max_font_size: f64,
+    /// Areas get an extra label for about every this many SVG pixels across them, 0 for one label each
+    #[wasm_bindgen(js_name = labelSpacing)]
+    pub label_spacing: f64,
+    /// Put the numbers of areas too small for them nearby, with a leader line to the area
+    pub callouts: bool,
+    /// Room around the image for callouts that don't fit nearby, in SVG pixels, 0 to keep them in the image
+    #[wasm_bindgen(js_name = calloutMargin)]
+    pub callout_margin: f64,
+    /// Paper for the PDF. Pages turn sideways to match the image.
+    #[wasm_bindgen(js_name = paperSize)]
+    pub paper_size: PaperSize,
+    /// Paper width for `PaperSize.Custom`, in millimeters
+    #[wasm_bindgen(js_name = paperWidth)]
+    pub paper_width: f64,
+    /// Paper height for `PaperSize.Custom`, in millimeters
+    #[wasm_bindgen(js_name = paperHeight)]
+    pub paper_height: f64,
+    /// Blank space around the edge of each PDF page, in millimeters
+    #[wasm_bindgen(js_name = pageMargin)]
+    pub page_margin: f64,
+}
+
+impl Default for PbnOptions {
+    fn default() -> PbnOptions {
+        PbnOptions {
+            k: 10,
+            min_area: 20,
+            min_width: 0,
+            max_regions: 0,
+            min_regions: 0,
+            merge_order: MergeOrder::Smallest,
+            merge_strategy: MergeStrategy::LongestBorder,
+            max_size: 600,
+            smoothing: Smoothing::None,
+            smoothing_radius: 2,
+            smoothing_strength: 25.0,
+            scale: 4,
+            output_format: FlatFormat::Png,
+            include_flat: false,
+            color_space: ColorSpace::Lab,
+            quantizer: QuantizerKind::KMeans,
+            max_iterations: 100,
+            seed: None,
+            palette: Vec::new(),
+            pigments: Vec::new(),
+            stroke_color: "black".to_string(),
+            stroke_width: 1.0,
+            curve_tolerance: 4.0,
+            min_font_size: 8.0,
+            max_font_size: 20.0,
+            label_spacing: 250.0,
+            callouts: false,
+            callout_margin: 40.0,
+            paper_size: PaperSize::A4,
+            paper_width: 210.0,
+            paper_height: 297.0,
+            page_margin: 10.0,
+        }
+    }
+}
+
+#[wasm_bindgen]
+impl PbnOptions {
+    /// Create options with the default settings
+    #[wasm_bindgen(constructor)]
+    pub fn new() -> PbnOptions {
+        PbnOptions::default()
+    }
+
+    /// Create options for a difficulty preset.
+    /// This sets the palette size, region count, minimum area and width, working size, smoothing and
+    /// label size together; everything else keeps its default.
+    /// The region targets are only reachable if the image is worked on large enough and small areas are
+    /// allowed to survive, so harder presets also raise `max_size` and lower `min_area`.
+    #[wasm_bindgen(js_name = forDifficulty)]
+    pub fn for_difficulty(difficulty: Difficulty) -> PbnOptions {
+        let (k, (min_regions, max_regions), min_area, min_width, max_size, smoothing_radius, smoothing_strength, font_sizes) =
+            match difficulty {
+                Difficulty::Beginner => (8, (150, 300), 12, 6, 1000, 4, 40.0, (14.0, 32.0)),
+                Difficulty::Intermediate => (14, (400, 700), 10, 4, 900, 3, 30.0, (11.0, 24.0)),
+                Difficulty::Expert => (20, (1000, 1500), 8, 3, 1000, 2, 20.0, (9.0, 18.0)),
+                Difficulty::Master => (30, (2000, 3000), 6, 2, 1200, 1, 15.0, (7.0, 14.0)),
+            };
+        PbnOptions {
+            k,
+            min_regions,
+            max_regions,
+            min_area,
+            min_width,
+            max_size,
+            smoothing: Smoothing::Bilateral,
+            smoothing_radius,
+            smoothing_strength,
+            min_font_size: font_sizes.0,
+            max_font_size: font_sizes.1,
+            ..PbnOptions::default()
+        }
+    }
+}
@@ -0,0 +1,152 @@
+use image::Rgb;
+
+use crate::{
+    color::{self, ColorSpace},
+    histogram::HistogramEntry,
+};
+
+/// A physical paint with a display name and the color it paints
+#[derive(Clone, Debug, PartialEq)]
+pub struct Paint {
+    pub name: String,
+    pub color: Rgb<u8>,
+}
+
+/// Parse a paint from a string. Accepted forms are:
+/// - `Cadmium Red=#E30022` or `Cadmium Red: #E30022`, a name and a hex color
+/// - `#E30022`, a bare hex color which is also used as the name
+/// - `crimson`, a CSS color name
+pub fn parse_paint(s: &str) -> Option<Paint> {
+    let s = s.trim();
+
+    // Named hex color
+    if let Some((name, hex)) = s.rsplit_once(['=', ':']) {
+        let name = name.trim();
+        if name.is_empty() {
+            return None;
+        }
+        return Some(Paint {
+            name: name.to_string(),
+            color: color::from_hex(hex)?,
+        });
+    }
+
+    // Bare hex color
+    if s.starts_with('#') {
+        let color = color::from_hex(s)?;
+        return Some(Paint {
+            name: color::to_hex(&color),
+            color,
+        });
+    }
+
+    // CSS color name
+    let lower = s.to_ascii_lowercase().replace([' ', '-', '_'], "");
+    NAMED_COLORS
+        .iter()
+        .find(|(name, _)| *name == lower)
+        .map(|(_, rgb)| Paint {
+            name: s.to_string(),
+            color: Rgb(*rgb),
+        })
+}
+
+/// Pick the `k` paints that best reproduce the colors in the histogram.
+/// Paints are added greedily, each time taking the one that lowers the total
+/// pixel weighted squared error the most. Returns indices into `paints`, in palette order.
+pub fn best_subset(hist: &[HistogramEntry], paints: &[Paint], k: usize, space: ColorSpace) -> Vec<usize> {
+    if k >= paints.len() {
+        return (0..paints.len()).collect();
+    }
+    println!("Choosing {k} of {} paints...", paints.len());
+
+    // Distance from every histogram color to every paint
+    let hist_colors = hist.iter().map(|(c, _)| space.from_rgb(c)).collect::<Vec<_>>();
+    let distances = paints
+        .iter()
+        .map(|p| {
+            let paint = space.from_rgb(&p.color);
+            hist_colors
+                .iter()
+                .map(|c| color::distance_sq(c, &paint))
+                .collect::<Vec<_>>()
+        })
+        .collect::<Vec<_>>();
+
+    // Squared distance from each histogram color to its nearest chosen paint
+    let mut nearest = vec![f64::MAX; hist.len()];
+    let mut chosen = Vec::<usize>::new();
+    for _ in 0..k {
+        let mut best = None;
+        let mut best_error = f64::MAX;
+        for (p, dists) in distances.iter().enumerate() {
+            if chosen.contains(&p) {
+                continue;
+            }
+            let error = hist
+                .iter()
+                .zip(nearest.iter().zip(dists.iter()))
+                .map(|((_, n), (a, b))| a.min(*b) * *n as f64)
+                .sum::<f64>();
+            if error < best_error {
+                best_error = error;
+                best = Some(p);
+            }
+        }
+
+        let Some(p) = best else {
+            break;
+        };
+        for (a, b) in nearest.iter_mut().zip(distances[p].iter()) {
+            *a = a.min(*b);
+        }
+        chosen.push(p);
+    }
+
+    chosen.sort_unstable();
+    chosen
+}
+
+// The CSS basic and common extended color names, lowercase without separators
+const NAMED_COLORS: [(&str, [u8; 3]); 40] = [
+    ("black", [0, 0, 0]),
+    ("white", [255, 255, 255]),
+    ("gray", [128, 128, 128]),
+    ("grey", [128, 128, 128]),
+    ("silver", [192, 192, 192]),
+    ("red", [255, 0, 0]),
+    ("maroon", [128, 0, 0]),
+    ("crimson", [220, 20, 60]),
+    ("orange", [255, 165, 0]),
+    ("gold", [255, 215, 0]),
+    ("yellow", [255, 255, 0]),
+    ("olive", [128, 128, 0]),
+    ("lime", [0, 255, 0]),
+    ("green", [0, 128, 0]),
+    ("darkgreen", [0, 100, 0]),
+    ("forestgreen", [34, 139, 34]),
+    ("teal", [0, 128, 128]),
+    ("cyan", [0, 255, 255]),
+    ("aqua", [0, 255, 255]),
+    ("skyblue", [135, 206, 235]),
+    ("blue", [0, 0, 255]),
+    ("navy", [0, 0, 128]),
+    ("royalblue", [65, 105, 225]),
+    ("purple", [128, 0, 128]),
+    ("violet", [238, 130, 238]),
+    ("magenta", [255, 0, 255]),
+    ("fuchsia", [255, 0, 255]),
+    ("pink", [255, 192, 203]),
+    ("hotpink", [255, 105, 180]),
+    ("brown", [165, 42, 42]),
+    ("chocolate", [210, 105, 30]),
+    ("sienna", [160, 82, 45]),
+    ("tan", [210, 180, 140]),
+    ("beige", [245, 245, 220]),
+    ("ivory", [255, 255, 240]),
+    ("khaki", [240, 230, 140]),
+    ("salmon", [250, 128, 114]),
+    ("coral", [255, 127, 80]),
+    ("indigo", [75, 0, 130]),
+    ("turquoise", [64, 224, 208]),
+];
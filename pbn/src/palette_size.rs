@@ -0,0 +1,177 @@
+use std::ops::RangeInclusive;
+
+use image::Rgb;
+use rand_chacha::ChaCha8Rng;
+use wasm_bindgen::prelude::*;
+
+use crate::{
+    color::{self, ColorSpace},
+    histogram::HistogramEntry,
+    kmeans,
+    quantize::Quantizer,
+};
+
+/// How to decide which palette size is best
+#[wasm_bindgen]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PaletteObjective {
+    /// The "knee" of the mean ΔE curve, past which more colors stop helping much
+    Elbow,
+    /// The smallest palette whose largest ΔE is under the target
+    MaxDeltaE,
+}
+
+/// The error of the palette for one value of k, measured as CIE76 ΔE in CIELAB
+#[derive(Clone, Copy, Debug)]
+pub struct PaletteSizeStat {
+    pub k: u32,
+    pub mean_delta_e: f64,
+    pub p95_delta_e: f64,
+    pub max_delta_e: f64,
+}
+
+/// The chosen palette size along with the error for every size that was tried
+#[wasm_bindgen]
+pub struct PaletteSizeReport {
+    k: u32,
+    stats: Vec<PaletteSizeStat>,
+}
+
+impl PaletteSizeReport {
+    /// The error statistics for every palette size that was tried
+    pub fn stats(&self) -> &[PaletteSizeStat] {
+        &self.stats
+    }
+}
+
+#[wasm_bindgen]
+impl PaletteSizeReport {
+    /// The chosen palette size
+    #[wasm_bindgen(getter)]
+    pub fn k(&self) -> u32 {
+        self.k
+    }
+
+    /// Every palette size that was tried
+    #[wasm_bindgen(getter)]
+    pub fn ks(&self) -> Vec<u32> {
+        self.stats.iter().map(|s| s.k).collect()
+    }
+
+    /// Pixel weighted mean ΔE for every palette size that was tried
+    #[wasm_bindgen(getter, js_name = meanDeltaE)]
+    pub fn mean_delta_e(&self) -> Vec<f64> {
+        self.stats.iter().map(|s| s.mean_delta_e).collect()
+    }
+
+    /// Pixel weighted 95th percentile ΔE for every palette size that was tried
+    #[wasm_bindgen(getter, js_name = p95DeltaE)]
+    pub fn p95_delta_e(&self) -> Vec<f64> {
+        self.stats.iter().map(|s| s.p95_delta_e).collect()
+    }
+
+    /// Maximum ΔE for every palette size that was tried
+    #[wasm_bindgen(getter, js_name = maxDeltaE)]
+    pub fn max_delta_e(&self) -> Vec<f64> {
+        self.stats.iter().map(|s| s.max_delta_e).collect()
+    }
+}
+
+/// Try every palette size in the range and pick the best one for the objective.
+/// `target_delta_e` is only used by `PaletteObjective::MaxDeltaE`.
+pub fn choose_k(
+    hist: &[HistogramEntry],
+    range: RangeInclusive<u32>,
+    objective: PaletteObjective,
+    target_delta_e: f64,
+    quantizer: &dyn Quantizer,
+    space: ColorSpace,
+    rng: &mut ChaCha8Rng,
+) -> PaletteSizeReport {
+    let min_k = (*range.start()).max(1);
+    let max_k = (*range.end()).max(min_k);
+    println!("Choosing palette size between {min_k} and {max_k} with {objective:?}...");
+
+    let stats = (min_k..=max_k)
+        .map(|k| {
+            let palette = quantizer.quantize(hist, k as usize, rng);
+            palette_error(hist, &palette, k, space)
+        })
+        .collect::<Vec<_>>();
+
+    let k = match objective {
+        PaletteObjective::Elbow => elbow(&stats),
+        PaletteObjective::MaxDeltaE => stats
+            .iter()
+            .find(|s| s.max_delta_e <= target_delta_e)
+            .unwrap_or(&stats[stats.len() - 1])
+            .k,
+    };
+    println!("Chose palette size {k}");
+
+    PaletteSizeReport { k, stats }
+}
+
+/// Measure how far each color in the histogram is from the palette color it gets replaced with.
+/// Colors are matched in the clustering color space, the same as `canvas::recolor`.
+fn palette_error(hist: &[HistogramEntry], palette: &[Rgb<u8>], k: u32, space: ColorSpace) -> PaletteSizeStat {
+    let palette_space = palette.iter().map(|c| space.from_rgb(c)).collect::<Vec<_>>();
+    let palette_lab = palette.iter().map(|c| ColorSpace::Lab.from_rgb(c)).collect::<Vec<_>>();
+
+    // ΔE and weight for each color in the histogram
+    let mut errors = hist
+        .iter()
+        .map(|(c, n)| {
+            let i = kmeans::nearest(&space.from_rgb(c), &palette_space);
+            (color::distance(&ColorSpace::Lab.from_rgb(c), &palette_lab[i]), *n as f64)
+        })
+        .collect::<Vec<_>>();
+    errors.sort_by(|a, b| a.0.total_cmp(&b.0));
+
+    let total = errors.iter().map(|(_, n)| n).sum::<f64>().max(1.0);
+    let mean_delta_e = errors.iter().map(|(e, n)| e * n).sum::<f64>() / total;
+    let max_delta_e = errors.last().map_or(0.0, |(e, _)| *e);
+
+    // Walk up the sorted errors until 95% of the pixels are covered
+    let mut p95_delta_e = max_delta_e;
+    let mut running = 0.0;
+    for (e, n) in errors.iter() {
+        running += n;
+        if running >= total * 0.95 {
+            p95_delta_e = *e;
+            break;
+        }
+    }
+
+    PaletteSizeStat {
+        k,
+        mean_delta_e,
+        p95_delta_e,
+        max_delta_e,
+    }
+}
+
+/// Find the knee of the error curve with the Kneedle method: normalize both axes
+/// and pick the point furthest below the line joining the first and last points
+fn elbow(stats: &[PaletteSizeStat]) -> u32 {
+    if stats.len() < 3 {
+        return stats[0].k;
+    }
+
+    let first = &stats[0];
+    let last = &stats[stats.len() - 1];
+    let k_range = (last.k - first.k) as f64;
+    let e_range = (first.mean_delta_e - last.mean_delta_e).max(f64::EPSILON);
+
+    let mut best = first.k;
+    let mut best_gap = f64::MIN;
+    for s in stats.iter() {
+        let x = (s.k - first.k) as f64 / k_range;
+        let y = (first.mean_delta_e - s.mean_delta_e) / e_range;
+        if y - x > best_gap {
+            best_gap = y - x;
+            best = s.k;
+        }
+    }
+    best
+}
@@ -0,0 +1,243 @@
+use image::Rgb;
+
+use crate::{
+    curve::{num, Curve, Segment},
+    options::PbnOptions,
+    palette,
+    svg::Drawing,
+};
+
+const PT_PER_MM: f64 = 72.0 / 25.4;
+
+// Every Helvetica digit is this wide, as a fraction of the font size
+const DIGIT_WIDTH: f64 = 0.556;
+
+// Legend rows are this many points apart
+const ROW_HEIGHT: f64 = 20.0;
+
+// Legend columns are never narrower than this many points, so the swatch, number and hex code fit
+const MIN_COLUMN_WIDTH: f64 = 90.0;
+
+/// Write a printable PDF of a puzzle. The first page has the outlines and numbers, fitted to the paper
+/// within the margins. The second page has a legend of every color, with its number, hex code and name,
+/// and a small preview of the finished picture. Colors that don't fit there carry on over more pages.
+/// Everything is vector, and the same input always gives the same bytes. The paper and margins must leave room to print on, see `check_paper`.
+pub fn write(drawing: &Drawing, colors: &[Rgb<u8>], hex: &[String], names: &[String], options: &PbnOptions) -> Vec<u8> {
+    println!("Writing PDF...");
+
+    // Turn the paper to match the image
+    let (w, h) = options.paper_size.size_mm(options);
+    let (mut pw, mut ph) = (w * PT_PER_MM, h * PT_PER_MM);
+    let m = drawing.margin;
+    let (dw, dh) = (drawing.width + 2.0 * m, drawing.height + 2.0 * m);
+    if (dw > dh) != (pw > ph) {
+        (pw, ph) = (ph, pw);
+    }
+    let margin = options.page_margin * PT_PER_MM;
+    let page = (margin, margin, pw - margin, ph - margin);
+
+    let mut pages = vec![outline_page(drawing, page, options)];
+    pages.extend(legend_pages(drawing, page, colors, hex, names));
+
+    // Catalog, pages, fonts, then each page and its contents
+    let media_box = format!("[0 0 {} {}]", num(pw), num(ph));
+    let resources = "<< /Font << /F1 3 0 R /F2 4 0 R >> >>";
+    let kids = (0..pages.len()).map(|i| format!("{} 0 R", 5 + 2 * i)).collect::<Vec<_>>().join(" ");
+    let mut objects = vec![
+        "<< /Type /Catalog /Pages 2 0 R >>".to_string(),
+        format!("<< /Type /Pages /Kids [{kids}] /Count {} >>", pages.len()),
+        "<< /Type /Font /Subtype /Type1 /BaseFont /Helvetica /Encoding /WinAnsiEncoding >>".to_string(),
+        "<< /Type /Font /Subtype /Type1 /BaseFont /Helvetica-Bold /Encoding /WinAnsiEncoding >>".to_string(),
+    ];
+    for (i, content) in pages.iter().enumerate() {
+        objects.push(format!(
+            "<< /Type /Page /Parent 2 0 R /MediaBox {media_box} /Resources {resources} /Contents {} 0 R >>",
+            6 + 2 * i
+        ));
+        objects.push(stream(content));
+    }
+
+    // Write the objects, keeping where each starts for the cross reference table
+    let mut out = String::from("%PDF-1.4\n");
+    let mut offsets = Vec::new();
+    for (i, object) in objects.iter().enumerate() {
+        offsets.push(out.len());
+        out.push_str(&format!("{} 0 obj\n{}\nendobj\n", i + 1, object));
+    }
+    let xref = out.len();
+    out.push_str(&format!("xref\n0 {}\n0000000000 65535 f \n", objects.len() + 1));
+    for offset in offsets {
+        out.push_str(&format!("{offset:010} 00000 n \n"));
+    }
+    out.push_str(&format!(
+        "trailer\n<< /Size {} /Root 1 0 R >>\nstartxref\n{}\n%%EOF\n",
+        objects.len() + 1,
+        xref
+    ));
+    out.into_bytes()
+}
+
+/// The outlines, leader lines and numbers, fitted into `page`
+fn outline_page(drawing: &Drawing, page: (f64, f64, f64, f64), options: &PbnOptions) -> String {
+    let m = drawing.margin;
+    let (k, tx, ty) = fit(drawing.width + 2.0 * m, drawing.height + 2.0 * m, page);
+    let (tx, ty) = (tx + m * k, ty - m * k);
+    let stroke = palette::parse_paint(&options.stroke_color).map_or(Rgb([0, 0, 0]), |p| p.color);
+
+    // Draw in SVG coordinates, flipping y, with lines at least a quarter point wide
+    let mut out = String::new();
+    out.push_str(&format!("q\n{} 0 0 {} {} {} cm\n1 J 1 j\n", fine(k), fine(-k), num(tx), num(ty)));
+    out.push_str(&format!("{} RG {} w\n", rgb(stroke), fine(options.stroke_width.max(0.25 / k))));
+    for edge in drawing.edges.iter() {
+        push_curve(&mut out, edge);
+    }
+    out.push_str("S\n");
+    out.push_str(&format!("{} w\n", fine((options.stroke_width / 2.0).max(0.25 / k))));
+    for (a, b) in drawing.labels.iter().filter_map(|l| l.leader_line()) {
+        out.push_str(&format!("{} {} m {} {} l\n", num(a.0), num(a.1), num(b.0), num(b.1)));
+    }
+    out.push_str("S\nQ\n");
+
+    // Numbers are placed in page coordinates so they stay upright, centered on their spot
+    out.push_str("0.3 g\n");
+    for label in drawing.labels.iter() {
+        let size = label.size * k;
+        let width = DIGIT_WIDTH * size * label.number.to_string().len() as f64;
+        let (x, y) = (tx + label.x * k - width / 2.0, ty - label.y * k - 0.35 * size);
+        out.push_str(&format!(
+            "BT /F1 {} Tf 1 0 0 1 {} {} Tm ({}) Tj ET\n",
+            num(size),
+            num(x),
+            num(y),
+            label.number
+        ));
+    }
+    out
+}
+
+/// The color legend in columns at the top of the first page, and a preview of the finished picture under
+/// it. Colors past what fits in columns of `MIN_COLUMN_WIDTH` go on more pages, using all of each.
+fn legend_pages(drawing: &Drawing, page: (f64, f64, f64, f64), colors: &[Rgb<u8>], hex: &[String], names: &[String]) -> Vec<String> {
+    let (x0, y0, x1, y1) = page;
+    let top = y1 - 32.0;
+    let bottom = y0 + (y1 - y0) * 0.45;
+    let max_columns = (((x1 - x0) / MIN_COLUMN_WIDTH).floor() as usize).max(1);
+    let fits = |bottom: f64| (((top - bottom) / ROW_HEIGHT).floor() as usize).max(1) * max_columns;
+
+    // The first page shares its room with the preview, the rest have the whole page
+    let mut pages = Vec::new();
+    let mut first = 0;
+    while first < colors.len() || pages.is_empty() {
+        let (title, bottom) = if pages.is_empty() { ("Color key", bottom) } else { ("Color key, continued", y0) };
+        let last = (first + fits(bottom)).min(colors.len());
+        let mut out = String::new();
+        out.push_str(&format!("0 g\nBT /F2 16 Tf 1 0 0 1 {} {} Tm ({title}) Tj ET\n", num(x0), num(y1 - 16.0)));
+        legend(&mut out, (x0, top, x1, bottom), first, &colors[first..last], hex, names);
+        pages.push(out);
+        first = last;
+    }
+    preview(&mut pages[0], drawing, (x0, y0, x1, bottom - 12.0), colors);
+    pages
+}
+
+/// Legend entries for colors `first..` in columns down the box, as many rows as fit, then another column
+fn legend(out: &mut String, (x0, top, x1, bottom): (f64, f64, f64, f64), first: usize, colors: &[Rgb<u8>], hex: &[String], names: &[String]) {
+    let rows = (((top - bottom) / ROW_HEIGHT).floor() as usize).max(1);
+    let columns = colors.len().div_ceil(rows).max(1);
+    let column_width = (x1 - x0) / columns as f64;
+    for (n, color) in colors.iter().enumerate() {
+        let i = first + n;
+        let x = x0 + (n / rows) as f64 * column_width;
+        let y = top - ((n % rows) + 1) as f64 * ROW_HEIGHT + 4.0;
+        out.push_str(&format!("{} rg 0 G 0.5 w {} {} 14 14 re B\n", rgb(*color), num(x), num(y)));
+        out.push_str(&format!("0 g\nBT /F2 10 Tf 1 0 0 1 {} {} Tm ({}) Tj ET\n", num(x + 20.0), num(y + 3.0), i + 1));
+        out.push_str(&format!("BT /F1 9 Tf 1 0 0 1 {} {} Tm ({}) Tj ET\n", num(x + 42.0), num(y + 3.0), text(&hex[i])));
+
+        // Paint names get whatever room is left in the column, at about half the font size per character
+        if names[i] != hex[i] {
+            let room = ((column_width - 90.0) / 4.5).max(0.0) as usize;
+            let name = names[i].chars().take(room).collect::<String>();
+            out.push_str(&format!("BT /F1 9 Tf 1 0 0 1 {} {} Tm ({}) Tj ET\n", num(x + 90.0), num(y + 3.0), text(&name)));
+        }
+    }
+}
+
+/// The finished picture fitted under a heading at the top of the box. It fills every area with its color,
+/// stroked in the same color to hide the seams.
+fn preview(out: &mut String, drawing: &Drawing, (x0, y0, x1, preview_top): (f64, f64, f64, f64), colors: &[Rgb<u8>]) {
+    out.push_str(&format!("BT /F2 10 Tf 1 0 0 1 {} {} Tm (Preview) Tj ET\n", num(x0), num(preview_top - 10.0)));
+    let (k, tx, ty) = fit(drawing.width, drawing.height, (x0, y0, x1, preview_top - 18.0));
+    out.push_str(&format!("q\n{} 0 0 {} {} {} cm\n1 j\n{} w\n", fine(k), fine(-k), num(tx), num(ty), fine(0.5 / k)));
+    for (rings, region) in drawing.areas.iter().zip(drawing.regions.iter()) {
+        if rings.is_empty() {
+            continue;
+        }
+        let color = rgb(colors[region.color as usize - 1]);
+        out.push_str(&format!("{color} rg {color} RG\n"));
+        for ring in rings.iter() {
+            push_curve(out, ring);
+            out.push_str("h\n");
+        }
+        out.push_str("B\n");
+    }
+    out.push_str("Q\n");
+}
+
+/// The scale and offset that fit a `width` by `height` drawing in the middle of a box, as
+/// (scale, left, top) so a point lands at (left + scale * x, top - scale * y)
+fn fit(width: f64, height: f64, (x0, y0, x1, y1): (f64, f64, f64, f64)) -> (f64, f64, f64) {
+    let k = ((x1 - x0) / width).min((y1 - y0) / height);
+    (k, x0 + (x1 - x0 - width * k) / 2.0, y1 - (y1 - y0 - height * k) / 2.0)
+}
+
+fn push_curve(out: &mut String, curve: &Curve) {
+    out.push_str(&format!("{} {} m\n", num(curve.start.0), num(curve.start.1)));
+    for segment in curve.segments.iter() {
+        match segment {
+            Segment::Line((x, y)) => out.push_str(&format!("{} {} l\n", num(*x), num(*y))),
+            Segment::Cubic((ax, ay), (bx, by), (x, y)) => out.push_str(&format!(
+                "{} {} {} {} {} {} c\n",
+                num(*ax),
+                num(*ay),
+                num(*bx),
+                num(*by),
+                num(*x),
+                num(*y)
+            )),
+        }
+    }
+}
+
+/// Format a scale factor, which needs more precision than a coordinate
+fn fine(v: f64) -> String {
+    let s = format!("{:.6}", v);
+    s.trim_end_matches('0').trim_end_matches('.').to_string()
+}
+
+/// A color as PDF color operands
+fn rgb(color: Rgb<u8>) -> String {
+    color.0.map(|c| num(c as f64 / 255.0)).join(" ")
+}
+
+/// Escape text for a PDF string. Characters past ASCII are written as octal escapes in the
+/// font's Latin encoding, and anything it can't show becomes a question mark.
+fn text(s: &str) -> String {
+    let mut out = String::new();
+    for c in s.chars() {
+        match c {
+            '(' | ')' | '\\' => {
+                out.push('\\');
+                out.push(c);
+            }
+            ' '..='~' => out.push(c),
+            c if (c as u32) >= 0xA0 && (c as u32) <= 0xFF => out.push_str(&format!("\\{:03o}", c as u32)),
+            _ => out.push('?'),
+        }
+    }
+    out
+}
+
+/// A content stream object
+fn stream(content: &str) -> String {
+    format!("<< /Length {} >>\nstream\n{}endstream", content.len(), content)
+}
@@ -0,0 +1,131 @@
+use std::{cmp::Ordering, collections::BinaryHeap};
+
+use crate::curve::Point;
+
+/// A square cell of the search, with its center's distance to the outline
+struct Cell {
+    x: f64,
+    y: f64,
+    half: f64,
+    dist: f64,
+    /// The furthest any point in the cell could be from the outline
+    max: f64,
+}
+
+impl Cell {
+    fn new(x: f64, y: f64, half: f64, rings: &[Vec<Point>]) -> Cell {
+        let dist = signed_distance((x, y), rings);
+        Cell {
+            x,
+            y,
+            half,
+            dist,
+            max: dist + half * std::f64::consts::SQRT_2,
+        }
+    }
+}
+
+impl PartialEq for Cell {
+    fn eq(&self, other: &Cell) -> bool {
+        self.max == other.max
+    }
+}
+
+impl Eq for Cell {}
+
+impl PartialOrd for Cell {
+    fn partial_cmp(&self, other: &Cell) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Cell {
+    fn cmp(&self, other: &Cell) -> Ordering {
+        self.max.total_cmp(&other.max)
+    }
+}
+
+/// Find the pole of inaccessibility of a polygon: the inside point furthest from its outline.
+/// `rings` is the outer ring followed by any holes. Returns the point and its distance to the outline,
+/// which is the radius of the largest circle that fits inside, to within `precision`.
+pub fn polylabel(rings: &[Vec<Point>], centroid: Point, precision: f64) -> (f64, f64, f64) {
+    let outer = &rings[0];
+    let (mut x0, mut y0, mut x1, mut y1) = (f64::MAX, f64::MAX, f64::MIN, f64::MIN);
+    for (x, y) in outer.iter() {
+        (x0, y0, x1, y1) = (x0.min(*x), y0.min(*y), x1.max(*x), y1.max(*y));
+    }
+    let size = (x1 - x0).min(y1 - y0);
+    if size <= 0.0 {
+        return (x0, y0, 0.0);
+    }
+
+    // Cover the bounding box with square cells
+    let half = size / 2.0;
+    let mut queue = BinaryHeap::new();
+    let mut x = x0;
+    while x < x1 {
+        let mut y = y0;
+        while y < y1 {
+            queue.push(Cell::new(x + half, y + half, half, rings));
+            y += size;
+        }
+        x += size;
+    }
+
+    // Start from the centroid, which is often a good guess
+    let mut best = Cell::new(centroid.0, centroid.1, 0.0, rings);
+    let center = Cell::new((x0 + x1) / 2.0, (y0 + y1) / 2.0, 0.0, rings);
+    if center.dist > best.dist {
+        best = center;
+    }
+
+    // Split the most promising cells until none could beat the best by more than the precision
+    while let Some(cell) = queue.pop() {
+        if cell.dist > best.dist {
+            best = Cell::new(cell.x, cell.y, 0.0, rings);
+        }
+        if cell.max - best.dist <= precision {
+            break;
+        }
+        let half = cell.half / 2.0;
+        for (dx, dy) in [(-1.0, -1.0), (1.0, -1.0), (-1.0, 1.0), (1.0, 1.0)] {
+            queue.push(Cell::new(cell.x + dx * half, cell.y + dy * half, half, rings));
+        }
+    }
+
+    (best.x, best.y, best.dist)
+}
+
+/// Distance from a point to the outline of a polygon, negative when the point is outside.
+/// A point inside a hole counts as outside.
+fn signed_distance(p: Point, rings: &[Vec<Point>]) -> f64 {
+    let mut inside = false;
+    let mut min = f64::MAX;
+    for ring in rings.iter() {
+        for (a, b) in ring.iter().zip(ring.iter().cycle().skip(1)) {
+            // Even-odd ray test to the right
+            if (a.1 > p.1) != (b.1 > p.1) && p.0 < (b.0 - a.0) * (p.1 - a.1) / (b.1 - a.1) + a.0 {
+                inside = !inside;
+            }
+            min = min.min(segment_dist_sq(p, *a, *b));
+        }
+    }
+    let dist = min.sqrt();
+    if inside {
+        dist
+    } else {
+        -dist
+    }
+}
+
+fn segment_dist_sq(p: Point, a: Point, b: Point) -> f64 {
+    let (dx, dy) = (b.0 - a.0, b.1 - a.1);
+    let len = dx * dx + dy * dy;
+    let t = if len == 0.0 {
+        0.0
+    } else {
+        (((p.0 - a.0) * dx + (p.1 - a.1) * dy) / len).clamp(0.0, 1.0)
+    };
+    let (x, y) = (a.0 + t * dx, a.1 + t * dy);
+    (p.0 - x).powi(2) + (p.1 - y).powi(2)
+}
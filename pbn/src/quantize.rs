@@ -0,0 +1,55 @@
+use image::Rgb;
+use rand_chacha::ChaCha8Rng;
+use wasm_bindgen::prelude::*;
+
+use crate::{
+    color::ColorSpace, histogram::HistogramEntry, kmeans, median_cut, octree, options::PbnOptions, wu,
+};
+
+/// A color quantization algorithm that reduces a color histogram to a palette
+pub trait Quantizer {
+    /// Pick at most `k` palette colors for the colors in the histogram
+    fn quantize(&self, hist: &[HistogramEntry], k: usize, rng: &mut ChaCha8Rng) -> Vec<Rgb<u8>>;
+}
+
+/// The available quantization algorithms
+#[wasm_bindgen]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum QuantizerKind {
+    /// K-means++ clustering; the slowest but usually the most accurate
+    KMeans,
+    /// Heckbert's median cut; deterministic and fast
+    MedianCut,
+    /// Octree color reduction; deterministic and fast
+    Octree,
+    /// Xiaolin Wu's variance minimization; deterministic and very fast
+    Wu,
+}
+
+impl QuantizerKind {
+    /// Create the quantizer for this algorithm, configured from the options.
+    /// The color space is only used by algorithms that compare colors directly.
+    pub fn quantizer(self, options: &PbnOptions) -> Box<dyn Quantizer> {
+        match self {
+            QuantizerKind::KMeans => Box::new(KMeans {
+                space: options.color_space,
+                max_iterations: options.max_iterations,
+            }),
+            QuantizerKind::MedianCut => Box::new(median_cut::MedianCut),
+            QuantizerKind::Octree => Box::new(octree::Octree),
+            QuantizerKind::Wu => Box::new(wu::Wu),
+        }
+    }
+}
+
+/// K-means clustering in a given color space
+pub struct KMeans {
+    pub space: ColorSpace,
+    pub max_iterations: u32,
+}
+
+impl Quantizer for KMeans {
+    fn quantize(&self, hist: &[HistogramEntry], k: usize, rng: &mut ChaCha8Rng) -> Vec<Rgb<u8>> {
+        kmeans::kmeans(hist, k, self.space, self.max_iterations, rng)
+    }
+}
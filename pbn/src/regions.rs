@@ -0,0 +1,191 @@
+use std::{cmp, collections::BTreeMap};
+
+use image::Rgb;
+
+use crate::{
+    components::{self, UnionFind},
+    indexed::IndexedImage,
+};
+
+/// A connected area of one color in a `RegionMap`
+#[derive(Clone, Debug)]
+pub struct Area {
+    /// Palette index of the area's color
+    pub color: u16,
+    /// Number of pixels in the area
+    pub pixels: u32,
+    /// The first pixel of the area in scan order
+    pub start: (u32, u32),
+    /// Bounding box as (min x, min y, max x, max y), inclusive
+    pub bbox: (u32, u32, u32, u32),
+    /// Number of pixel edges between the area and other areas or the image border
+    pub perimeter: u32,
+    /// Neighboring area ids and how many pixel edges are shared with each
+    pub neighbors: BTreeMap<u32, u32>,
+    x_sum: u64,
+    y_sum: u64,
+}
+
+impl Area {
+    fn empty() -> Area {
+        Area {
+            color: 0,
+            pixels: 0,
+            start: (u32::MAX, u32::MAX),
+            bbox: (u32::MAX, u32::MAX, 0, 0),
+            perimeter: 0,
+            neighbors: BTreeMap::new(),
+            x_sum: 0,
+            y_sum: 0,
+        }
+    }
+
+    /// The mean position of the area's pixels
+    pub fn centroid(&self) -> (f64, f64) {
+        (
+            self.x_sum as f64 / self.pixels as f64,
+            self.y_sum as f64 / self.pixels as f64,
+        )
+    }
+}
+
+/// The areas of a label map and how they touch.
+/// Every pixel has the id of its area, and ids are numbered in scan order.
+pub struct RegionMap {
+    width: u32,
+    height: u32,
+    labels: Vec<u32>,
+    areas: Vec<Area>,
+    merged: UnionFind,
+}
+
+impl RegionMap {
+    /// Find the 4-connected areas of an image and measure them
+    pub fn new(img: &IndexedImage) -> RegionMap {
+        let (width, height) = img.dimensions();
+        let (labels, sizes) = components::label(img);
+        let mut areas = sizes
+            .iter()
+            .map(|pixels| Area {
+                pixels: *pixels,
+                ..Area::empty()
+            })
+            .collect::<Vec<_>>();
+
+        for y in 0..height {
+            for x in 0..width {
+                let i = (y * width + x) as usize;
+                let area = &mut areas[labels[i] as usize];
+                if area.start.0 == u32::MAX {
+                    area.start = (x, y);
+                    area.color = img.get(x, y);
+                }
+                area.bbox = (
+                    area.bbox.0.min(x),
+                    area.bbox.1.min(y),
+                    area.bbox.2.max(x),
+                    area.bbox.3.max(y),
+                );
+                area.x_sum += x as u64;
+                area.y_sum += y as u64;
+
+                // Edges on the image border count towards the perimeter
+                area.perimeter += (x == 0) as u32
+                    + (y == 0) as u32
+                    + (x == width - 1) as u32
+                    + (y == height - 1) as u32;
+
+                // Count the edges shared with the right and lower neighbors
+                let right = (x + 1 < width).then(|| i + 1);
+                let down = (y + 1 < height).then(|| i + width as usize);
+                for n in [right, down].into_iter().flatten() {
+                    let (a, b) = (labels[i], labels[n]);
+                    if a != b {
+                        *areas[a as usize].neighbors.entry(b).or_insert(0) += 1;
+                        *areas[b as usize].neighbors.entry(a).or_insert(0) += 1;
+                        areas[a as usize].perimeter += 1;
+                        areas[b as usize].perimeter += 1;
+                    }
+                }
+            }
+        }
+
+        let merged = UnionFind::new(areas.len());
+        RegionMap {
+            width,
+            height,
+            labels,
+            areas,
+            merged,
+        }
+    }
+
+    pub fn dimensions(&self) -> (u32, u32) {
+        (self.width, self.height)
+    }
+
+    /// Every area, indexed by id. Areas merged into another are left empty.
+    pub fn areas(&self) -> &[Area] {
+        &self.areas
+    }
+
+    pub fn area(&self, id: u32) -> &Area {
+        &self.areas[id as usize]
+    }
+
+    /// The id of the area a pixel was first labeled with, before any merging
+    pub fn label(&self, x: u32, y: u32) -> u32 {
+        self.labels[(y * self.width + x) as usize]
+    }
+
+    /// Change the color of an area
+    pub fn set_color(&mut self, id: u32, color: u16) {
+        self.areas[id as usize].color = color;
+    }
+
+    /// Merge area `other` into area `into`, which keeps its id and color.
+    /// `other` is left empty.
+    pub fn merge(&mut self, other: u32, into: u32) {
+        if other == into {
+            return;
+        }
+        self.merged.union_into(other, into);
+
+        let taken = std::mem::replace(&mut self.areas[other as usize], Area::empty());
+
+        // Move the neighbors over, dropping the border the two areas shared
+        let mut shared = 0;
+        for (n, len) in taken.neighbors.iter() {
+            self.areas[*n as usize].neighbors.remove(&other);
+            if *n == into {
+                shared = *len;
+                continue;
+            }
+            *self.areas[*n as usize].neighbors.entry(into).or_insert(0) += len;
+            *self.areas[into as usize].neighbors.entry(*n).or_insert(0) += len;
+        }
+
+        let area = &mut self.areas[into as usize];
+        area.pixels += taken.pixels;
+        area.start = cmp::min_by_key(area.start, taken.start, |(x, y)| (*y, *x));
+        area.bbox = (
+            area.bbox.0.min(taken.bbox.0),
+            area.bbox.1.min(taken.bbox.1),
+            area.bbox.2.max(taken.bbox.2),
+            area.bbox.3.max(taken.bbox.3),
+        );
+        area.perimeter = area.perimeter + taken.perimeter - 2 * shared;
+        area.x_sum += taken.x_sum;
+        area.y_sum += taken.y_sum;
+    }
+
+    /// Turn the map back into an image, painting each pixel with the color of the area it ended up in
+    pub fn into_image(mut self, palette: Vec<Rgb<u8>>) -> IndexedImage {
+        let indices = self
+            .labels
+            .iter()
+            .map(|l| self.areas[self.merged.find(*l) as usize].color)
+            .collect();
+        IndexedImage::new(self.width, self.height, indices, palette)
+    }
+}
@@ -1,466 +1,419 @@
-use std::collections::VecDeque;
-
-use image::{Rgb, RgbImage};
-use std::cmp;
+use wasm_bindgen::prelude::*;
+
+use crate::{
+    curve::{self, Curve},
+    indexed::IndexedImage,
+    layout::{self, Label},
+    options::PbnOptions,
+    polylabel::polylabel,
+    regions::RegionMap,
+    trace::{self, Boundaries, Ring},
+};
+
+/// A painted area of the puzzle
+#[wasm_bindgen]
+#[derive(Clone, Copy, Debug)]
+pub struct Region {
+    /// Matches the `shape-{id}` and `label-{id}` ids in the SVG
+    pub id: u32,
+    /// The number painted in the area, where color `n` is palette entry `n - 1`
+    pub color: u32,
+    /// Size of the area in SVG pixels
+    pub area: u32,
+    #[wasm_bindgen(js_name = labelX)]
+    pub label_x: f64,
+    #[wasm_bindgen(js_name = labelY)]
+    pub label_y: f64,
+    /// Radius of the largest circle around the label that fits in the area, in SVG pixels
+    #[wasm_bindgen(js_name = labelRadius)]
+    pub label_radius: f64,
+    /// Font size of the label, fitted to its circle
+    #[wasm_bindgen(js_name = fontSize)]
+    pub font_size: f64,
+    /// The label was moved outside the area, with a leader line to it
+    pub callout: bool,
+    /// The label has a spot clear of every other label. If not, it's drawn at its spot anyway.
+    pub labeled: bool,
+    /// The area this one sits in a hole of, if any
+    pub parent: Option<u32>,
+}
 
-/// Convert a flat image to an SVG string.
-/// Returns the SVG string and a list of colors used in the image.
-pub fn img_to_svg(img: &RgbImage) -> (String, Vec<String>) {
-    println!("Converting image to SVG...");
-    let mut out = String::with_capacity(1000);
+/// The vector shapes of a puzzle, ready to be written out as SVG or PDF. Coordinates are in SVG pixels.
+pub struct Drawing {
+    pub width: f64,
+    pub height: f64,
+    /// Room around the image for callouts, or 0 if none went there
+    pub margin: f64,
+    /// Every boundary between areas once, smoothed into curves
+    pub edges: Vec<Curve>,
+    /// The rings of each area by id, made of the same curves as the edges, with the outer ring first
+    pub areas: Vec<Vec<Curve>>,
+    /// The numbers to draw
+    pub labels: Vec<Label>,
+    pub regions: Vec<Region>,
+}
 
+/// Trace a label map into vector shapes, scaling it up by `scale`.
+/// Boundaries follow the cracks between pixels. Each edge is smoothed into curves once, and areas reuse
+/// those curves, so neighbors stay watertight. Labels are sized to fit their area, big areas get extra
+/// labels, and labels are laid out so they don't overlap. With callouts on, areas too small for a label
+/// get theirs nearby with a leader line to it. Curve, font and callout settings come from the options.
+pub fn draw(img: &IndexedImage, scale: u32, options: &PbnOptions) -> Drawing {
     // Get image dimensions
     let (width, height) = img.dimensions();
 
-    // SVG Header
-    out.push_str(&format!(
-        "<svg xmlns=\"http://www.w3.org/2000/svg\" version=\"1.1\" width=\"{}\" height=\"{}\">\n",
-        width, height
-    ));
-
-    // Create map of nums to colors
-    let mut color_map = std::collections::HashMap::<&Rgb<u8>, u32>::new();
-    let mut color_list = Vec::<String>::new();
-    let mut color_count = 0;
-    for y in 0..height {
-        for x in 0..width {
-            let color = img.get_pixel(x, y);
-            if !color_map.contains_key(&color) {
-                color_count += 1;
-                color_map.insert(color, color_count);
-                color_list.push(rgb_to_hex(color));
+    // Find every area and trace its boundaries
+    let map = RegionMap::new(img);
+    let boundaries = trace::trace(&map);
+    let dist = distances(&map);
+    let mut regions = Vec::<Region>::new();
+
+    // Smooth every edge once
+    let edge_curve = |edge: &trace::Edge, tolerance: f64| {
+        let points = simplify(&edge.points)
+            .iter()
+            .map(|(x, y)| ((x * scale) as f64, (y * scale) as f64))
+            .collect::<Vec<_>>();
+        curve::fit(&points, tolerance)
+    };
+    let mut edges = boundaries.edges.iter().map(|edge| edge_curve(edge, options.curve_tolerance)).collect::<Vec<_>>();
+
+    // A thin area can flatten to nothing when all its edges are smoothed, so keep those edges on the pixels
+    for ring in boundaries.rings.iter().flatten() {
+        if ring_curve(ring, &edges).area() == 0.0 {
+            for (edge, _) in ring.iter() {
+                edges[*edge] = edge_curve(&boundaries.edges[*edge], 0.0);
             }
         }
     }
 
-    // Draw borders and numbers
-    // Loop through all pixels
-    let mut visited = vec![vec![false; img.height() as usize]; img.width() as usize];
-    let mut area_count = 0;
-    for y in 0..img.height() {
-        for x in 0..img.width() {
-            // If visited, ignore
-            if visited[x as usize][y as usize] {
-                continue;
+    // Join each area's rings from its edges, and find where its numbers go, in scan order
+    let mut labels = Vec::<Label>::new();
+    let mut areas = Vec::<Vec<Curve>>::new();
+    for (id, area) in map.areas().iter().enumerate() {
+        areas.push(boundaries.rings[id].iter().map(|ring| ring_curve(ring, &edges)).collect());
+
+        // The number, then any extra numbers the area has room for
+        let col_index = area.color as u32 + 1;
+        let (lx, ly, radius) = label_spot(&boundaries, id, area.centroid());
+        let room = radius * scale as f64;
+        labels.push(Label {
+            area: id as u32,
+            index: 0,
+            x: lx * scale as f64,
+            y: ly * scale as f64,
+            size: font_size(room, col_index, options),
+            number: col_index,
+            room,
+            leader: None,
+        });
+        regions.push(Region {
+            id: id as u32,
+            color: col_index,
+            area: area.pixels * scale * scale,
+            label_x: 0.0,
+            label_y: 0.0,
+            label_radius: room,
+            font_size: 0.0,
+            callout: false,
+            labeled: false,
+            parent: Some(boundaries.parents[id]).filter(|p| *p != trace::OUTSIDE),
+        });
+        if options.label_spacing > 0.0 {
+            // Only where a label at the minimum size fits
+            let digits = col_index.to_string().len();
+            let fits = options.min_font_size * (0.36 * (digits * digits) as f64 + 1.0).sqrt() / 2.0;
+            let min_dist = (fits / scale as f64 + 0.5).ceil() as u32;
+            let spacing = options.label_spacing / scale as f64;
+            for (n, (x, y, d)) in extra_spots(&map, &dist, id as u32, (lx, ly), spacing, min_dist).into_iter().enumerate() {
+                let room = (d as f64 - 0.5) * scale as f64;
+                labels.push(Label {
+                    area: id as u32,
+                    index: n + 1,
+                    x: (x as f64 + 0.5) * scale as f64,
+                    y: (y as f64 + 0.5) * scale as f64,
+                    size: font_size(room, col_index, options),
+                    number: col_index,
+                    room,
+                    leader: None,
+                });
             }
+        }
+    }
 
-            // Find the borders of the current area
-            let borders = find_area_borders(img, &mut visited, x, y);
+    // Lay the numbers out so none overlap, moving, shrinking or calling them out as needed
+    let (labels, labeled) = layout::arrange(&map, &dist, scale, labels, options);
+    for label in labels.iter().filter(|l| l.index == 0) {
+        let region = &mut regions[label.area as usize];
+        (region.label_x, region.label_y, region.font_size) = (label.x, label.y, label.size);
+        region.callout = label.leader.is_some();
+        region.labeled = labeled[label.area as usize];
+    }
 
-            // If the borders are empty, ignore
-            if borders.is_empty() {
-                println!(
-                    "WARNING: Could not find borders for area with ({}, {})",
-                    x, y
-                );
-                continue;
-            }
+    // Make room around the image if any callouts went in the margin
+    let (w, h) = ((width * scale) as f64, (height * scale) as f64);
+    let in_margin = labels.iter().any(|l| {
+        let (x0, y0, x1, y1) = l.bbox();
+        x1 <= 0.0 || y1 <= 0.0 || x0 >= w || y0 >= h
+    });
+
+    Drawing {
+        width: w,
+        height: h,
+        margin: if in_margin { options.callout_margin } else { 0.0 },
+        edges,
+        areas,
+        labels,
+        regions,
+    }
+}
 
-            // Get the position of the number
-            let (nx, ny) = get_num_pos(&borders, img.height() as usize);
+/// Convert a label map to an SVG string, scaling it up by `scale`.
+/// Label `n` is palette color `n - 1`. Returns the SVG string and the areas it's made of.
+/// Every edge is drawn once in the `edges` path under the `shape-{id}` areas, so neighbors share their
+/// outline. Big areas get extra labels with ids `label-{id}-{n}`, and callouts get a `leader-{id}` line.
+/// Stroke, curve, font and callout settings come from the options.
+pub fn img_to_svg(img: &IndexedImage, scale: u32, options: &PbnOptions) -> (String, Vec<Region>) {
+    println!("Converting image to SVG...");
+    let drawing = draw(img, scale, options);
+    let mut out = String::with_capacity(1000);
 
-            // Optimize the borders
-            let borders = borders
-                .iter()
-                .map(|b| optimize_border(b.clone()))
-                .collect::<Vec<_>>();
+    // SVG Header, with room around the image for callouts in the margin
+    let (w, h, m) = (drawing.width, drawing.height, drawing.margin);
+    if m > 0.0 {
+        out.push_str(&format!(
+            "<svg xmlns=\"http://www.w3.org/2000/svg\" version=\"1.1\" width=\"{}\" height=\"{}\" viewBox=\"{} {} {} {}\">\n",
+            w + 2.0 * m,
+            h + 2.0 * m,
+            -m,
+            -m,
+            w + 2.0 * m,
+            h + 2.0 * m
+        ));
+    } else {
+        out.push_str(&format!(
+            "<svg xmlns=\"http://www.w3.org/2000/svg\" version=\"1.1\" width=\"{}\" height=\"{}\">\n",
+            w, h
+        ));
+    }
 
-            // Write the borders to SVG
-            out.push_str(&format!("<path stroke=\"black\" fill=\"transparent\" stroke-width=\"1\" id=\"shape-{}\" fill-rule=\"evenodd\" class=\"unfilled\" d=\"", area_count));
-            for border in borders.iter() {
-                out.push_str(&format!(" M{} {}", border[0].0, border[0].1));
-                for (x, y) in border.iter().skip(1) {
-                    out.push_str(&format!(" L {} {}", x, y));
-                }
-                out.push_str(" Z");
-            }
-            out.push_str("\" />\n");
+    // Draw every edge once, under the areas so painting an area covers its outline
+    out.push_str(&format!(
+        "<path id=\"edges\" stroke=\"{}\" fill=\"none\" stroke-width=\"{}\" stroke-linejoin=\"round\" d=\"",
+        escape_attr(&options.stroke_color),
+        options.stroke_width
+    ));
+    for curve in drawing.edges.iter() {
+        out.push_str(&format!(" M{} {}", curve::num(curve.start.0), curve::num(curve.start.1)));
+        curve.push_segments(&mut out);
+    }
+    out.push_str("\" />\n");
+
+    // Draw the areas, in scan order
+    for (id, rings) in drawing.areas.iter().enumerate() {
+        out.push_str(&format!(
+            "<path stroke=\"none\" fill=\"transparent\" stroke-width=\"{}\" id=\"shape-{}\" fill-rule=\"nonzero\" class=\"unfilled\" d=\"",
+            options.stroke_width, id
+        ));
+        for ring in rings.iter() {
+            out.push_str(&format!(" M{} {}", curve::num(ring.start.0), curve::num(ring.start.1)));
+            ring.push_segments(&mut out);
+            out.push_str(" Z");
+        }
+        out.push_str("\" />\n");
+    }
 
-            // Draw the number
-            let col_index = color_map.get(&img.get_pixel(x, y)).unwrap();
+    // Draw the numbers over every area, with leader lines under them
+    for label in drawing.labels.iter() {
+        if let Some(((x1, y1), (x2, y2))) = label.leader_line() {
             out.push_str(&format!(
-                "<text id=\"label-{}\" x=\"{}\" y=\"{}\" font-size=\"10\">{}</text>\n",
-                area_count, nx, ny, col_index
+                "<line id=\"leader-{}\" class=\"leader\" x1=\"{}\" y1=\"{}\" x2=\"{}\" y2=\"{}\" stroke=\"{}\" stroke-width=\"{}\" />\n",
+                label.area,
+                round(x1),
+                round(y1),
+                round(x2),
+                round(y2),
+                escape_attr(&options.stroke_color),
+                options.stroke_width / 2.0
             ));
-
-            // Increment the area count
-            area_count += 1;
         }
     }
+    for label in drawing.labels.iter() {
+        let id = match label.index {
+            0 => format!("label-{}", label.area),
+            n => format!("label-{}-{}", label.area, n - 1),
+        };
+        out.push_str(&format!(
+            "<text id=\"{}\" x=\"{}\" y=\"{}\" font-size=\"{}\" text-anchor=\"middle\" dominant-baseline=\"central\">{}</text>\n",
+            id, label.x, label.y, label.size, label.number
+        ));
+    }
 
     // SVG Footer
     out.push_str("</svg>\n");
 
-    (out, color_list)
+    (out, drawing.regions)
 }
 
-/// Convert an RGB color to a hex string.
-fn rgb_to_hex(rgb: &Rgb<u8>) -> String {
-    format!("#{:02X}{:02X}{:02X}", rgb[0], rgb[1], rgb[2])
+/// Escape a string for use inside a double quoted XML attribute.
+fn escape_attr(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('"', "&quot;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
 }
 
-/// Find the borders of an area, returning a list of list of border points.
-/// The list of border points represents the outer border. Subsequent lists
-/// represent holes in the area.
-fn find_area_borders(
-    img: &RgbImage,
-    visited: &mut Vec<Vec<bool>>,
-    ox: u32,
-    oy: u32,
-) -> Vec<Vec<(usize, usize)>> {
-    let mut border = Vec::<Vec<(usize, usize)>>::new();
-
-    // Variables for flood filling
-    let mut queue = VecDeque::<(usize, usize)>::new();
-    queue.push_back((ox as usize, oy as usize));
-    let color = img.get_pixel(ox, oy);
-
-    while !queue.is_empty() {
-        let (x, y) = queue.pop_front().unwrap();
-
-        // If visited, ignore
-        if visited[x][y] {
-            continue;
-        }
-
-        // If wrong color, ignore
-        if img.get_pixel(x as u32, y as u32) != color {
-            continue;
-        }
-
-        // Mark as visited
-        visited[x][y] = true;
-
-        // Check if the pixel is a border pixel
-        if is_border(img, x, y) {
-            // If it is, follow the border
-            let b = follow_edge(img, visited, &mut queue, x, y);
-            border.push(b);
-        }
-
-        // Flood fill in the 4 cardinal directions
-        for (dx, dy) in DIRECTIONS.iter() {
-            let nx = x as i32 + dx;
-            let ny = y as i32 + dy;
-
-            // Only add if in bounds
-            if nx < 0 || ny < 0 || nx >= img.width() as i32 || ny >= img.height() as i32 {
-                continue;
-            }
-
-            queue.push_back((nx as usize, ny as usize));
-        }
-    }
-
-    border
+/// The biggest font size that fits a number in a circle of `radius`, within the options' limits
+fn font_size(radius: f64, number: u32, options: &PbnOptions) -> f64 {
+    round(layout::fit_size(radius, number).min(options.max_font_size).max(options.min_font_size))
 }
 
-fn is_border(img: &RgbImage, x: usize, y: usize) -> bool {
-    // Check if the pixel is on the edge of the image
-    if x == 0 || y == 0 || x == img.width() as usize - 1 || y == img.height() as usize - 1 {
-        return true;
-    }
-
-    // Check the 8 pixels around the current pixel and see if it's a different color
-    let color = img.get_pixel(x as u32, y as u32);
-    let mut different = false;
-    for dx in -1..=1 {
-        for dy in -1..=1 {
-            if dx == 0 && dy == 0 {
-                continue;
-            }
-
-            let nx = x as i32 + dx;
-            let ny = y as i32 + dy;
-
-            // If out of bounds, assume it's a border
-            if nx < 0 || ny < 0 || nx >= img.width() as i32 || ny >= img.height() as i32 {
-                different = true;
-                break;
-            }
-
-            let ncolor = img.get_pixel(nx as u32, ny as u32);
-            if ncolor != color {
-                different = true;
-                break;
-            }
+/// A ring as one closed curve, made of the curves of its edges
+fn ring_curve(ring: &Ring, edges: &[Curve]) -> Curve {
+    let mut out = Curve {
+        start: (0.0, 0.0),
+        segments: Vec::new(),
+    };
+    for (i, (edge, reversed)) in ring.iter().enumerate() {
+        let curve = if *reversed { edges[*edge].reversed() } else { edges[*edge].clone() };
+        if i == 0 {
+            out.start = curve.start;
         }
+        out.segments.extend(curve.segments);
     }
-
-    different
+    out
 }
 
-// 4 cardinal directions in a clockwise order
-const DIRECTIONS: [(i32, i32); 4] = [(0, -1), (1, 0), (0, 1), (-1, 0)];
-
-/// Follow the edge of an area starting at a given point.
-/// This function will return a list of points that represent the border of the area,
-/// stopping when it reaches the starting point.
-/// We assume that each pixel can only touch 1 border.
-fn follow_edge(
-    img: &RgbImage,
-    visited: &mut Vec<Vec<bool>>,
-    queue: &mut VecDeque<(usize, usize)>,
-    x: usize,
-    y: usize,
-) -> Vec<(usize, usize)> {
-    let mut current = (x, y);
-    let mut parent = (x, y);
-    let mut border = Vec::<(usize, usize)>::new();
-    let mut found;
-    border.push(current);
-    let in_color = img.get_pixel(x as u32, y as u32);
-
-    loop {
-        // Mark the current pixel as visited
-        visited[current.0][current.1] = true;
-
-        // Check the 4 cardinal directions
-        found = false;
-        for d in DIRECTIONS {
-            let nx = current.0 as i32 + d.0;
-            let ny = current.1 as i32 + d.1;
-
-            // println!("Checking ({:?}, {:?}) for d = {:?}", nx, ny, d);
-
-            // If the new pixel is the starting pixel, we are done
-            if (nx as usize, ny as usize) == (x, y) {
-                return border;
-            }
-
-            // Check if the new pixel is out of bounds
-            if nx < 0 || ny < 0 || nx >= img.width() as i32 || ny >= img.height() as i32 {
-                continue;
-            }
-
-            // Check to make sure we don't go backwards
-            if parent == (nx as usize, ny as usize) {
-                continue;
-            }
-
-            // Check if the new pixel is the same color
-            let ncolor = img.get_pixel(nx as u32, ny as u32);
-            if ncolor != in_color {
-                continue;
-            }
-
-            // If the new pixel is not a border, continue
-            // Also add it to the flood fill queue
-            if !is_border(img, nx as usize, ny as usize) {
-                queue.push_back((nx as usize, ny as usize));
-                continue;
-            }
-
-            // If the new pixel is a border, add it to the border list, mark it as visited
-            // and set the new pixel as the current pixel
-            parent = current;
-            current = (nx as usize, ny as usize);
-            border.push(current);
-            visited[nx as usize][ny as usize] = true;
-            found = true;
-            // println!("Adding {:?}", current);
-            break;
-        }
-
-        // If we reach here and haven't found anything, we have a problem
-        if !found {
-            println!(
-                "WARNING: Area is not closed at {:?} (cannot find border to follow; this should not happen...)",
-                current
-            );
-            return border;
+/// Remove points in the middle of straight lines, keeping both ends
+fn simplify(points: &[(u32, u32)]) -> Vec<(u32, u32)> {
+    let mut out = vec![points[0]];
+    for i in 1..points.len() - 1 {
+        let (a, b, c) = (out[out.len() - 1], points[i], points[i + 1]);
+        let turns = (b.0 as i64 - a.0 as i64) * (c.1 as i64 - b.1 as i64)
+            != (b.1 as i64 - a.1 as i64) * (c.0 as i64 - b.0 as i64);
+        if turns {
+            out.push(b);
         }
     }
+    out.push(points[points.len() - 1]);
+    out
 }
 
-/// Get the position of the number for a given area.
-/// The function will return the position of the number as a tuple of (x, y).
-fn get_num_pos(border_list: &Vec<Vec<(usize, usize)>>, max_height: usize) -> (usize, usize) {
-    // Get the first border
-    let outer_border = &border_list[0];
-
-    // Find the centroid of the outer polygon
-    let mut x_sum = 0;
-    let mut y_sum = 0;
-    for (x, y) in outer_border {
-        x_sum += x;
-        y_sum += y;
-    }
-    let mut centroid = (x_sum / outer_border.len(), y_sum / outer_border.len());
-
-    // Flatten all borders into one
-    let borders = border_list
+/// Find the point in an area that's furthest from its outline and holes, to put the number on.
+/// Returns the point and its distance to the outline, in pixels.
+fn label_spot(boundaries: &Boundaries, id: usize, centroid: (f64, f64)) -> (f64, f64, f64) {
+    let rings = boundaries.rings[id]
         .iter()
-        .flatten()
-        .collect::<Vec<&(usize, usize)>>();
+        .map(|ring| {
+            let mut points = boundaries.ring_points(ring);
+            points.push(points[0]);
+            let mut points = simplify(&points);
+            points.pop();
+            points.into_iter().map(|(x, y)| (x as f64, y as f64)).collect()
+        })
+        .collect::<Vec<_>>();
+
+    // The centroid is of pixel corners, so move it to the middle of the pixels
+    let (x, y, radius) = polylabel(&rings, (centroid.0 + 0.5, centroid.1 + 0.5), 0.25);
+    (round(x), round(y), radius)
+}
 
-    // If centroid is on a border, move it in one of the directions that isn't a border
-    if borders.contains(&&centroid) {
-        let mut new_centroid = centroid;
-        for (dx, dy) in DIRECTIONS.iter() {
-            let nx = centroid.0 as i32 + dx;
-            let ny = centroid.1 as i32 + dy;
-            if !borders.contains(&&(nx as usize, ny as usize)) {
-                new_centroid = (nx as usize, ny as usize);
-                break;
+/// Chessboard distance from every pixel to the nearest pixel of another area or the image border,
+/// where pixels on the border of their area are 1
+fn distances(map: &RegionMap) -> Vec<u32> {
+    let (width, height) = map.dimensions();
+    let (w, h) = (width as usize, height as usize);
+    let mut dist = vec![u32::MAX; w * h];
+    for y in 0..h {
+        for x in 0..w {
+            let id = map.label(x as u32, y as u32);
+            let edge = x == 0
+                || y == 0
+                || x == w - 1
+                || y == h - 1
+                || map.label(x as u32 - 1, y as u32) != id
+                || map.label(x as u32 + 1, y as u32) != id
+                || map.label(x as u32, y as u32 - 1) != id
+                || map.label(x as u32, y as u32 + 1) != id;
+            if edge {
+                dist[y * w + x] = 1;
             }
         }
-        if centroid == new_centroid {
-            panic!("Could not find a new centroid");
-        }
-        centroid = new_centroid;
     }
 
-    // Count the number of times a ray to the positive x direction intersects the borders
-    let border_hits = borders
-        .iter()
-        .filter(|&b| (*b).1 == centroid.1 && (*b).0 > centroid.0)
-        .count();
-    let inside = border_hits % 2 == 1;
-
-    // Loop through list of borders and find borders in the 4 directions
-    let mut left = Vec::<(usize, usize)>::new();
-    let mut right = Vec::<(usize, usize)>::new();
-    let mut up = Vec::<(usize, usize)>::new();
-    let mut down = Vec::<(usize, usize)>::new();
-
-    for border in border_list.iter() {
-        for (x, y) in border {
-            if *y == centroid.1 {
-                if *x < centroid.0 {
-                    left.push((*x, *y));
-                } else {
-                    right.push((*x, *y));
-                }
-            }
-            if *x == centroid.0 {
-                if *y < centroid.1 {
-                    up.push((*x, *y));
-                } else {
-                    down.push((*x, *y));
+    // One pass forwards and one backwards, each looking at the neighbors already visited
+    let neighbors = [(-1, -1), (0, -1), (1, -1), (-1, 0)];
+    for y in 0..h {
+        for x in 0..w {
+            for (dx, dy) in neighbors {
+                let (nx, ny) = (x as i64 + dx, y as i64 + dy);
+                if nx >= 0 && ny >= 0 && nx < w as i64 {
+                    let n = dist[ny as usize * w + nx as usize];
+                    dist[y * w + x] = dist[y * w + x].min(n.saturating_add(1));
                 }
             }
         }
     }
-
-    // Sort all lists by distance to centroid
-    left.sort_by(|a, b| b.0.cmp(&a.0));
-    right.sort_by(|a, b| a.0.cmp(&b.0));
-    up.sort_by(|a, b| b.1.cmp(&a.1));
-    down.sort_by(|a, b| a.1.cmp(&b.1));
-
-    // Make list of possible points
-    let mut possible_points = Vec::<((usize, usize), (usize, usize))>::new();
-
-    if inside {
-        // If inside, take first point from left and right, then up and down
-        if left.len() > 0 && right.len() > 0 {
-            possible_points.push((left[0], right[0]));
-        }
-        if up.len() > 0 && down.len() > 0 {
-            possible_points.push((up[0], down[0]));
-        }
-    } else {
-        // Otherwise, take two points from each side
-        if left.len() > 1 {
-            possible_points.push((left[0], left[1]));
-        }
-        if right.len() > 1 {
-            possible_points.push((right[0], right[1]));
-        }
-        if up.len() > 1 {
-            possible_points.push((up[0], up[1]));
-        }
-        if down.len() > 1 {
-            possible_points.push((down[0], down[1]));
-        }
-    }
-
-    let possible_midpoints = possible_points.iter().map(|(a, b)| ((a.0 + b.0) / 2, (a.1 + b.1) / 2));
-
-    // Find the midpoint with the smallest distance to each of the 4 edges
-    let mut min_dist = usize::MAX;
-    let mut min_index = 0;
-    for (i, midpoint) in possible_midpoints.enumerate() {
-        let dist = dist_to_borders(&midpoint, &border_list);
-        if dist < min_dist {
-            min_dist = dist;
-            min_index = i;
-        }
-    }
-    let max_pair = possible_points[min_index];
-
-    // Return the midpoint of the pair of points
-    let d = 2;
-    let nx = cmp::max((max_pair.0 .0 + max_pair.1 .0) / 2 - d, d) as usize;
-    let ny = cmp::min((max_pair.0 .1 + max_pair.1 .1) / 2 + d, max_height - d) as usize;
-    // println!("Point: {:?}", (nx, ny));
-    // println!("==========================================================");
-
-    (nx, ny)
-}
-
-/// Find the distance of a midpoint to the first border in the 4 cardinal directions
-fn dist_to_borders(midpoint: &(usize, usize), border_list: &Vec<Vec<(usize, usize)>>) -> usize {
-    let mut left = (usize::MAX, usize::MAX);
-    let mut right = (usize::MAX, usize::MAX);
-    let mut up = (usize::MAX, usize::MAX);
-    let mut down = (usize::MAX, usize::MAX);
-    for border in border_list {
-        for (x, y) in border {
-            if *y == midpoint.1 {
-                if *x < midpoint.0 {
-                    left = cmp::min(left, (*x, *y));
-                } else {
-                    right = cmp::min(right, (*x, *y));
-                }
-            }
-            if *x == midpoint.0 {
-                if *y < midpoint.1 {
-                    up = cmp::min(up, (*x, *y));
-                } else {
-                    down = cmp::min(down, (*x, *y));
+    for y in (0..h).rev() {
+        for x in (0..w).rev() {
+            for (dx, dy) in neighbors {
+                let (nx, ny) = (x as i64 - dx, y as i64 - dy);
+                if nx >= 0 && ny < h as i64 && nx < w as i64 {
+                    let n = dist[ny as usize * w + nx as usize];
+                    dist[y * w + x] = dist[y * w + x].min(n.saturating_add(1));
                 }
             }
         }
     }
-    if left == (usize::MAX, usize::MAX) || right == (usize::MAX, usize::MAX) || up == (usize::MAX, usize::MAX) || down == (usize::MAX, usize::MAX) {
-        println!("WARNING: Could not find all borders around midpoint {:?}", midpoint);
-        return usize::MAX;
-    }
-    return right.0 - left.0 + down.1 - up.1;
+    dist
 }
 
-/// Optimize the border by removing points that are in the same line.
-fn optimize_border(border: Vec<(usize, usize)>) -> Vec<(usize, usize)> {
-    if border.len() < 3 {
-        return border;
+/// Extra pixels in an area to put its number on, besides the first label.
+/// Each new spot is the pixel furthest from the labels so far, until none is `spacing` pixels away,
+/// which spreads the labels evenly. Only pixels at least `min_dist` from the border are used.
+/// Returns each pixel and its distance to the border.
+fn extra_spots(
+    map: &RegionMap,
+    dist: &[u32],
+    id: u32,
+    first: (f64, f64),
+    spacing: f64,
+    min_dist: u32,
+) -> Vec<(u32, u32, u32)> {
+    let area = map.area(id);
+    let (x0, y0, x1, y1) = area.bbox;
+    if (x1 - x0 + 1).max(y1 - y0 + 1) as f64 <= spacing {
+        return Vec::new();
     }
 
-    let mut optimized = Vec::<(usize, usize)>::new();
-    optimized.push(border[0]);
-    let mut dir = (0, 0);
-    let mut curr = border[0];
-
-    // Loop through the border in order
-    for b in border.iter().skip(1) {
-        // Check direction of next border
-        let next_dir = (b.0 as i32 - curr.0 as i32, b.1 as i32 - curr.1 as i32);
-
-        // If direction is in the same direction, continue
-        // Current item part of the same current border line
-        if dir == (0, 0) || dir == next_dir {
-            dir = next_dir;
-            curr = *b;
-            continue;
+    let width = map.dimensions().0;
+    let mut candidates = Vec::new();
+    for y in y0..=y1 {
+        for x in x0..=x1 {
+            let d = dist[(y * width + x) as usize];
+            if map.label(x, y) == id && d >= min_dist {
+                let (cx, cy) = (x as f64 + 0.5, y as f64 + 0.5);
+                let to_first = (cx - first.0).hypot(cy - first.1);
+                candidates.push((x, y, d, to_first));
+            }
         }
+    }
 
-        // Otherwise, that means that we've changed directions
-        // Add the "curr" point to the list and update the direction
-        optimized.push(curr);
-        dir = next_dir;
-        curr = *b;
+    let mut spots = Vec::new();
+    while let Some(best) = candidates.iter().max_by(|a, b| a.3.total_cmp(&b.3)).copied() {
+        if best.3 < spacing {
+            break;
+        }
+        spots.push((best.0, best.1, best.2));
+        for c in candidates.iter_mut() {
+            let to_best = (c.0 as f64 - best.0 as f64).hypot(c.1 as f64 - best.1 as f64);
+            c.3 = c.3.min(to_best);
+        }
     }
+    spots
+}
 
-    optimized
+/// Round to two decimals, to keep the SVG short
+fn round(v: f64) -> f64 {
+    (v * 100.0).round() / 100.0
 }
@@ -0,0 +1,262 @@
+use std::collections::HashMap;
+
+use crate::regions::RegionMap;
+
+/// The label used for the outside of the image
+pub const OUTSIDE: u32 = u32::MAX;
+
+/// A run of cracks between pixels that separates the same two areas, ending at junctions.
+/// The `left` area is on the left when walking the points in order, with y pointing down.
+#[derive(Clone, Debug)]
+pub struct Edge {
+    pub left: u32,
+    /// The area on the right, or `OUTSIDE` for the image border
+    pub right: u32,
+    /// Corner points between pixels, from 0 to the image size. Closed loops repeat the first point at the end.
+    pub points: Vec<(u32, u32)>,
+}
+
+/// A closed boundary of an area, as edges in order. `true` means the edge is walked backwards.
+pub type Ring = Vec<(usize, bool)>;
+
+/// Every boundary of a label map, with each edge stored once and shared by the areas on either side
+pub struct Boundaries {
+    pub edges: Vec<Edge>,
+    /// The rings of each area by id. The first ring is the outer boundary, which goes counterclockwise on
+    /// screen, and the rest are holes, which go clockwise. This makes the `nonzero` and `evenodd` fill
+    /// rules agree.
+    pub rings: Vec<Vec<Ring>>,
+    /// The area each area sits in a hole of, or `OUTSIDE` for areas that aren't inside another one
+    pub parents: Vec<u32>,
+}
+
+impl Boundaries {
+    /// The areas sitting directly in the holes of an area, or in no hole for `OUTSIDE`
+    pub fn children(&self, id: u32) -> Vec<u32> {
+        (0..self.parents.len() as u32)
+            .filter(|child| self.parents[*child as usize] == id)
+            .collect()
+    }
+
+    /// Twice the signed area of a ring, with y pointing down: negative for counterclockwise on screen
+    pub fn ring_area(&self, ring: &Ring) -> i64 {
+        let points = self.ring_points(ring);
+        points
+            .iter()
+            .zip(points.iter().cycle().skip(1))
+            .map(|(a, b)| a.0 as i64 * b.1 as i64 - b.0 as i64 * a.1 as i64)
+            .sum()
+    }
+
+    /// The corner points of a ring, without repeating the first point at the end
+    pub fn ring_points(&self, ring: &Ring) -> Vec<(u32, u32)> {
+        let mut points = Vec::new();
+        for (i, (edge, reversed)) in ring.iter().enumerate() {
+            let edge = &self.edges[*edge];
+            // Each edge starts where the last one ended
+            let skip = if i == 0 { 0 } else { 1 };
+            if *reversed {
+                points.extend(edge.points.iter().rev().skip(skip));
+            } else {
+                points.extend(edge.points.iter().skip(skip));
+            }
+        }
+        points.pop();
+        points
+    }
+}
+
+// Directions to walk along cracks: up, right, down, left
+const DIRECTIONS: [(i64, i64); 4] = [(0, -1), (1, 0), (0, 1), (-1, 0)];
+
+/// Trace the boundaries of every area along the cracks between pixels.
+/// Rings keep the area on their left, which makes outer rings go counterclockwise on screen.
+/// Diagonal pixels of one area are kept apart, matching the 4-connected areas of the map.
+pub fn trace(map: &RegionMap) -> Boundaries {
+    println!("Tracing area boundaries...");
+    let (width, height) = map.dimensions();
+    let stride = width as usize + 1;
+
+    // The area of a pixel, or OUTSIDE past the image border
+    let label = |x: i64, y: i64| {
+        if x < 0 || y < 0 || x >= width as i64 || y >= height as i64 {
+            OUTSIDE
+        } else {
+            map.label(x as u32, y as u32)
+        }
+    };
+    // The pixels to the left and right of the crack leaving a corner in a direction
+    let sides = |x: i64, y: i64, d: usize| match d {
+        0 => (label(x - 1, y - 1), label(x, y - 1)),
+        1 => (label(x, y - 1), label(x, y)),
+        2 => (label(x, y), label(x - 1, y)),
+        _ => (label(x - 1, y), label(x - 1, y - 1)),
+    };
+    // Corners where three or more cracks meet split boundaries into edges
+    let is_junction = |x: i64, y: i64| {
+        (0..4)
+            .filter(|d| {
+                let (l, r) = sides(x, y, *d);
+                l != r
+            })
+            .count()
+            > 2
+    };
+
+    let mut walked = vec![0u8; stride * (height as usize + 1)];
+    let mut edges = Vec::<Edge>::new();
+    let mut edge_keys = HashMap::<(Crack, Crack), usize>::new();
+    let mut rings = vec![Vec::<Ring>::new(); map.areas().len()];
+
+    // Pixel sides with the pixel on the left: top, left, bottom, right
+    let starts = [((1, 0), 3), ((0, 0), 2), ((0, 1), 1), ((1, 1), 0)];
+    for y in 0..height as i64 {
+        for x in 0..width as i64 {
+            let id = label(x, y);
+            for ((ox, oy), d) in starts {
+                let (sx, sy) = (x + ox, y + oy);
+                let (left, right) = sides(sx, sy, d);
+                let bit = 1 << d;
+                if left == right || walked[sy as usize * stride + sx as usize] & bit != 0 {
+                    continue;
+                }
+
+                // Walk the ring, hugging the area so diagonal pixels stay apart
+                let mut corners = Vec::<(i64, i64)>::new();
+                let (mut cx, mut cy, mut cd) = (sx, sy, d);
+                loop {
+                    walked[cy as usize * stride + cx as usize] |= 1 << cd;
+                    corners.push((cx, cy));
+                    cx += DIRECTIONS[cd].0;
+                    cy += DIRECTIONS[cd].1;
+
+                    // Turn left if possible, then go straight, then turn right
+                    cd = [(cd + 3) % 4, cd, (cd + 1) % 4]
+                        .into_iter()
+                        .find(|nd| {
+                            let (l, r) = sides(cx, cy, *nd);
+                            l == id && r != id
+                        })
+                        .expect("a ring always continues");
+                    if (cx, cy, cd) == (sx, sy, d) {
+                        break;
+                    }
+                }
+
+                // Start at a junction, or the first corner in scan order for a ring without one
+                let start = corners
+                    .iter()
+                    .position(|(x, y)| is_junction(*x, *y))
+                    .unwrap_or_else(|| {
+                        let min = corners.iter().min_by_key(|(x, y)| (*y, *x)).unwrap();
+                        corners.iter().position(|c| c == min).unwrap()
+                    });
+                corners.rotate_left(start);
+                corners.push(corners[0]);
+
+                // Split the ring into edges at junctions, reusing edges the area on the other side already made
+                let mut ring = Ring::new();
+                let mut from = 0;
+                for i in 1..corners.len() {
+                    let (x, y) = corners[i];
+                    if i + 1 < corners.len() && !is_junction(x, y) {
+                        continue;
+                    }
+                    let points = corners[from..=i]
+                        .iter()
+                        .map(|(x, y)| (*x as u32, *y as u32))
+                        .collect::<Vec<_>>();
+                    let (px, py) = corners[from];
+                    let (_, right) = sides(px, py, direction(corners[from], corners[from + 1]));
+                    ring.push(shared_edge(&mut edges, &mut edge_keys, id, right, points));
+                    from = i;
+                }
+                rings[id as usize].push(ring);
+            }
+        }
+    }
+
+    let mut boundaries = Boundaries {
+        edges,
+        rings,
+        parents: Vec::new(),
+    };
+
+    // Keeping the area on the left makes outer rings counterclockwise and holes clockwise, so the sign
+    // of the area tells them apart. Put the outer ring first.
+    for id in 0..boundaries.rings.len() {
+        let mut rings = std::mem::take(&mut boundaries.rings[id]);
+        rings.sort_by_key(|ring| boundaries.ring_area(ring) > 0);
+        boundaries.rings[id] = rings;
+    }
+    boundaries.parents = parents(map, &boundaries);
+    boundaries
+}
+
+/// Find the area each area sits in a hole of.
+/// The crack above an area's first pixel is on its outer ring. If the area above is outside that ring,
+/// the crack is on one of its holes and it's the parent. Otherwise the two areas are side by side in the same
+/// hole, and share a parent. The area above comes first in scan order, so its parent is already known.
+fn parents(map: &RegionMap, boundaries: &Boundaries) -> Vec<u32> {
+    let mut parents = vec![OUTSIDE; boundaries.rings.len()];
+    for (id, area) in map.areas().iter().enumerate() {
+        let (x, y) = area.start;
+        if area.pixels == 0 || y == 0 {
+            continue;
+        }
+        let above = map.label(x, y - 1);
+
+        // The edge with the crack, and whether it's on a hole of the area above
+        let top = crack((x, y), (x + 1, y));
+        let edge = boundaries.rings[id][0]
+            .iter()
+            .map(|(edge, _)| *edge)
+            .find(|edge| {
+                let points = &boundaries.edges[*edge].points;
+                points.windows(2).any(|w| crack(w[0], w[1]) == top)
+            })
+            .expect("the crack above the first pixel is on the outer ring");
+        let in_hole = boundaries.rings[above as usize]
+            .iter()
+            .skip(1)
+            .any(|ring| ring.iter().any(|(e, _)| *e == edge));
+        parents[id] = if in_hole { above } else { parents[above as usize] };
+    }
+    parents
+}
+
+/// The direction from one corner to the next
+fn direction(a: (i64, i64), b: (i64, i64)) -> usize {
+    DIRECTIONS
+        .iter()
+        .position(|d| (a.0 + d.0, a.1 + d.1) == b)
+        .unwrap()
+}
+
+/// Find the edge with these points walked either way, or add it.
+/// An edge is keyed by its first and last cracks, which are the same whichever way it's walked.
+fn shared_edge(
+    edges: &mut Vec<Edge>,
+    keys: &mut HashMap<(Crack, Crack), usize>,
+    left: u32,
+    right: u32,
+    points: Vec<(u32, u32)>,
+) -> (usize, bool) {
+    let n = points.len();
+    let (first, last) = (crack(points[0], points[1]), crack(points[n - 2], points[n - 1]));
+    let key = (first.min(last), first.max(last));
+    if let Some(i) = keys.get(&key) {
+        return (*i, edges[*i].left != left);
+    }
+
+    keys.insert(key, edges.len());
+    edges.push(Edge { left, right, points });
+    (edges.len() - 1, false)
+}
+
+/// A crack between two neighboring corners, with the corners in a fixed order
+type Crack = ((u32, u32), (u32, u32));
+
+fn crack(a: (u32, u32), b: (u32, u32)) -> Crack {
+    (a.min(b), a.max(b))
+}
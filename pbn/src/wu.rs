@@ -0,0 +1,193 @@
+use image::Rgb;
+use rand_chacha::ChaCha8Rng;
+
+use crate::{histogram::HistogramEntry, quantize::Quantizer};
+
+// Colors are binned to 5 bits per channel, with an extra zero row for the cumulative moments
+const SIDE: usize = 33;
+
+/// Xiaolin Wu's color quantizer.
+/// Splits the RGB cube into boxes that minimize the weighted color variance,
+/// using cumulative moments so every box statistic is constant time.
+pub struct Wu;
+
+/// An axis aligned box in the binned color cube, exclusive of the lower bounds
+#[derive(Clone, Copy, Default)]
+struct Cube {
+    lo: [usize; 3],
+    hi: [usize; 3],
+}
+
+/// Cumulative moments of the histogram over the binned color cube
+struct Moments {
+    weight: Vec<i64>,
+    sum: [Vec<i64>; 3],
+    sq: Vec<f64>,
+}
+
+fn index(r: usize, g: usize, b: usize) -> usize {
+    (r * SIDE + g) * SIDE + b
+}
+
+impl Moments {
+    fn new(hist: &[HistogramEntry]) -> Moments {
+        let size = SIDE * SIDE * SIDE;
+        let mut m = Moments {
+            weight: vec![0; size],
+            sum: [vec![0; size], vec![0; size], vec![0; size]],
+            sq: vec![0.0; size],
+        };
+
+        // Bin the histogram
+        for (color, n) in hist.iter() {
+            let n = *n as i64;
+            let i = index(
+                (color[0] >> 3) as usize + 1,
+                (color[1] >> 3) as usize + 1,
+                (color[2] >> 3) as usize + 1,
+            );
+            m.weight[i] += n;
+            for c in 0..3 {
+                m.sum[c][i] += color[c] as i64 * n;
+            }
+            m.sq[i] += color.0.iter().map(|v| (*v as f64) * (*v as f64)).sum::<f64>() * n as f64;
+        }
+
+        // Turn the bins into cumulative moments, one axis at a time
+        for axis in 0..3 {
+            for r in 1..SIDE {
+                for g in 1..SIDE {
+                    for b in 1..SIDE {
+                        let prev = match axis {
+                            0 => index(r - 1, g, b),
+                            1 => index(r, g - 1, b),
+                            _ => index(r, g, b - 1),
+                        };
+                        let i = index(r, g, b);
+                        m.weight[i] += m.weight[prev];
+                        for c in 0..3 {
+                            m.sum[c][i] += m.sum[c][prev];
+                        }
+                        m.sq[i] += m.sq[prev];
+                    }
+                }
+            }
+        }
+
+        m
+    }
+
+    /// Sum of a cumulative moment over a cube, by inclusion-exclusion
+    fn volume<T>(cube: &Cube, m: &[T]) -> T
+    where
+        T: Copy + std::ops::Add<Output = T> + std::ops::Sub<Output = T>,
+    {
+        let (lo, hi) = (cube.lo, cube.hi);
+        m[index(hi[0], hi[1], hi[2])] - m[index(hi[0], hi[1], lo[2])] - m[index(hi[0], lo[1], hi[2])]
+            + m[index(hi[0], lo[1], lo[2])]
+            - m[index(lo[0], hi[1], hi[2])]
+            + m[index(lo[0], hi[1], lo[2])]
+            + m[index(lo[0], lo[1], hi[2])]
+            - m[index(lo[0], lo[1], lo[2])]
+    }
+
+    /// Weight and channel sums of a cube
+    fn stats(&self, cube: &Cube) -> (i64, [i64; 3]) {
+        (
+            Moments::volume(cube, &self.weight),
+            [
+                Moments::volume(cube, &self.sum[0]),
+                Moments::volume(cube, &self.sum[1]),
+                Moments::volume(cube, &self.sum[2]),
+            ],
+        )
+    }
+
+    /// Weighted variance of the colors inside a cube
+    fn variance(&self, cube: &Cube) -> f64 {
+        let (w, s) = self.stats(cube);
+        if w == 0 {
+            return 0.0;
+        }
+        let sq = Moments::volume(cube, &self.sq);
+        sq - s.iter().map(|v| (*v as f64) * (*v as f64)).sum::<f64>() / w as f64
+    }
+
+    /// Try to split a cube in two along the axis that best reduces variance.
+    /// Returns the two halves, or None if the cube can't be split.
+    fn cut(&self, cube: &Cube) -> Option<(Cube, Cube)> {
+        let (whole_w, whole_s) = self.stats(cube);
+
+        let mut best: Option<(f64, usize, usize)> = None;
+        for axis in 0..3 {
+            for pos in cube.lo[axis] + 1..cube.hi[axis] {
+                let mut lower = *cube;
+                lower.hi[axis] = pos;
+                let (lw, ls) = self.stats(&lower);
+                let uw = whole_w - lw;
+                if lw == 0 || uw == 0 {
+                    continue;
+                }
+                let us = [whole_s[0] - ls[0], whole_s[1] - ls[1], whole_s[2] - ls[2]];
+                let score = ls.iter().map(|v| (*v as f64) * (*v as f64)).sum::<f64>() / lw as f64
+                    + us.iter().map(|v| (*v as f64) * (*v as f64)).sum::<f64>() / uw as f64;
+                if best.is_none_or(|(max, _, _)| score > max) {
+                    best = Some((score, axis, pos));
+                }
+            }
+        }
+
+        let (_, axis, pos) = best?;
+        let mut lower = *cube;
+        let mut upper = *cube;
+        lower.hi[axis] = pos;
+        upper.lo[axis] = pos;
+        Some((lower, upper))
+    }
+}
+
+impl Quantizer for Wu {
+    fn quantize(&self, hist: &[HistogramEntry], k: usize, _rng: &mut ChaCha8Rng) -> Vec<Rgb<u8>> {
+        println!("Running Wu quantization with k={k} on {} unique colors...", hist.len());
+        let moments = Moments::new(hist);
+
+        let mut cubes = vec![Cube {
+            lo: [0; 3],
+            hi: [SIDE - 1; 3],
+        }];
+        let mut variances = vec![moments.variance(&cubes[0])];
+
+        // Always split the cube with the highest variance next
+        while cubes.len() < k {
+            let next = (0..cubes.len())
+                .filter(|i| variances[*i] > 0.0)
+                .max_by(|a, b| variances[*a].total_cmp(&variances[*b]));
+            let Some(next) = next else {
+                break;
+            };
+
+            match moments.cut(&cubes[next]) {
+                Some((lower, upper)) => {
+                    cubes[next] = lower;
+                    variances[next] = moments.variance(&lower);
+                    cubes.push(upper);
+                    variances.push(moments.variance(&upper));
+                }
+                // Cubes that can't be split are never picked again
+                None => variances[next] = 0.0,
+            }
+        }
+
+        // Each cube's color is the average of the colors inside it
+        cubes
+            .iter()
+            .filter_map(|cube| {
+                let (w, s) = moments.stats(cube);
+                if w == 0 {
+                    return None;
+                }
+                Some(Rgb(s.map(|v| ((v + w / 2) / w) as u8)))
+            })
+            .collect()
+    }
+}